@@ -28,7 +28,20 @@ import argparse
 # TODO: we could do the pre-processing in the Rust implementation before saving to be more efficient,
 #       although we'd have to save it as comment metadata in primitive file formats (CSV)...
 
-def readDataValuesFromCSVFile(filename):
+# reverses the escaping of backslashes, newlines and carriage returns psrec does to metadata values
+def unescapeMetadataValue(value):
+    unescaped = ""
+    i = 0
+    while i < len(value):
+        if value[i] == '\\' and i + 1 < len(value):
+            unescaped += {'n': '\n', 'r': '\r'}.get(value[i + 1], value[i + 1])
+            i += 2
+        else:
+            unescaped += value[i]
+            i += 1
+    return unescaped
+
+def readDataValuesFromCSVFile(filename, seriesName):
     timeValues = []
     cpuValues = []
     rssValues = []
@@ -41,18 +54,55 @@ def readDataValuesFromCSVFile(filename):
     maxCPUValue = 0.0
     cpuType = None
     systemThreads = None
+    normaliseThreads = None
+    # times the recorded process was switched to its replacement, when following restarts
+    pidSwitchTimes = []
+
+    # indices of the value columns, which can be worked out from the column header comment if there is one,
+    # as optional columns (i.e. absolute timestamps) might be present
+    timeIndex = 0
+    cpuIndex = 1
+    rssIndex = 2
+    threadCountIndex = 3
+    # for recordings of multiple processes in the 'long' layout, the index of the series name column
+    seriesIndex = None
 
     fData = open(filename, "r")
     for line in fData:
         if len(line) == 0:
             continue
+        if line.startswith("# ") and ',' in line:
+            # it should be the column header comment
+            columnNames = [x.strip() for x in line[2:].split(',')]
+            seriesIndex = columnNames.index("Series") if "Series" in columnNames else None
+            # recordings of multiple processes in the 'wide' layout have the value columns of each series
+            # prefixed with the series name, so use the requested series, or the first one.
+            prefix = ""
+            if seriesIndex is None and "CPU Usage" not in columnNames:
+                if seriesName is not None:
+                    prefix = seriesName + " "
+                else:
+                    cpuColumns = [x for x in columnNames if x.endswith(" CPU Usage")]
+                    if len(cpuColumns) > 0:
+                        prefix = cpuColumns[0][:-len("CPU Usage")]
+            if (prefix + "CPU Usage") in columnNames and (prefix + "RSS") in columnNames:
+                timeIndex = columnNames.index("Time elapsed") if "Time elapsed" in columnNames else 0
+                cpuIndex = columnNames.index(prefix + "CPU Usage")
+                rssIndex = columnNames.index(prefix + "RSS")
+                threadCountIndex = columnNames.index(prefix + "Thread Count") if (prefix + "Thread Count") in columnNames else None
+            elif prefix != "":
+                print("Error: Series '%s' was not found in the recording." % (prefix.strip()))
+                return None
+            continue
         if line[0] == '#':
             # see if it's a 'metadata' comment
             if len(line) >= 4 and line[1] == '@':
                 # it should be a metadata item, starting after the '#@ ' string,
                 # so try and interpret it...
                 metadata = line[2:]
-                metadata_items = [x.strip() for x in metadata.split(':')]
+                metadata_items = [x.strip() for x in metadata.split(':', 1)]
+                if len(metadata_items) > 1:
+                    metadata_items[1] = unescapeMetadataValue(metadata_items[1])
                 if metadata_items[0] == "cputype":
                     if metadata_items[1] == "normalised":
                         cpuType = "normalised"
@@ -62,15 +112,31 @@ def readDataValuesFromCSVFile(filename):
                         print("Unexpected 'cputype' metadata value.")
                 elif metadata_items[0] == "systhreads":
                     systemThreads = int(metadata_items[1])
+                elif metadata_items[0] == "normthreads":
+                    normaliseThreads = int(metadata_items[1])
+                elif metadata_items[0] == "pidswitch":
+                    pidSwitchTimes.append(float(metadata_items[1].split(',')[0]))
             continue
         # is this a good idea? Might be better to error...
         if not ',' in line:
             continue
 
         items = line.split(',')
-        time = items[0]
-        cpu = items[1]
-        rss = items[2]
+
+        if seriesIndex is not None:
+            # only use the rows of the requested series, or the first one
+            if seriesName is None:
+                seriesName = items[seriesIndex]
+            if items[seriesIndex] != seriesName:
+                continue
+
+        time = items[timeIndex]
+        cpu = items[cpuIndex].strip()
+        rss = items[rssIndex].strip()
+
+        # there won't be values for series at times the process wasn't running
+        if len(cpu) == 0:
+            continue
 
         cpu = float(cpu)
 
@@ -87,8 +153,8 @@ def readDataValuesFromCSVFile(filename):
         cpuValues.append(cpu)
         rssValues.append(rssVal)
 
-        if len(items) > 3:
-            threadCountsValues.append(int(items[3]))
+        if threadCountIndex is not None and len(items) > threadCountIndex:
+            threadCountsValues.append(int(items[threadCountIndex]))
     
     # if there were no valid values, exit out...
     if len(timeValues) == 0:
@@ -101,12 +167,14 @@ def readDataValuesFromCSVFile(filename):
         # Also resize the numbers
         for i in range(len(timeValues)):
             timeValues[i] /= 3600.0
+        pidSwitchTimes = [x / 3600.0 for x in pidSwitchTimes]
     elif timeValues[-1] > (60.0 * 5.0):
         # Use minutes
         timeUnit = "m"
         # Also resize the numbers
         for i in range(len(timeValues)):
             timeValues[i] /= 60.0
+        pidSwitchTimes = [x / 60.0 for x in pidSwitchTimes]
     
     if rssUnit == "gb":
         # Resize numbers to GB size
@@ -119,10 +187,45 @@ def readDataValuesFromCSVFile(filename):
         # we don't know, so try and guess...
         cpuType = "absolute" if maxCPUValue > 102.0 else "normalised"
         
+    # the thread count normalised values were normalised against, if they were
+    if normaliseThreads is None:
+        normaliseThreads = systemThreads
+
     values = {'tv':timeValues, 'cv':cpuValues, 'rv':rssValues, 'tcv':threadCountsValues, 'tu':timeUnit, 'ru':rssUnit,
-              'cpuType':cpuType, 'sysThreads':systemThreads, 'mcv':maxCPUValue}
+              'cpuType':cpuType, 'sysThreads':systemThreads, 'normThreads':normaliseThreads, 'mcv':maxCPUValue,
+              'pst':pidSwitchTimes}
     return values
 
+# converts the CPU values to the requested type ("absolute" or "normalised"), optionally re-normalising
+# against a different thread count than the one the values were originally normalised against.
+def convertCPUValues(dataValues, targetCPUType, targetThreads):
+    currentThreads = dataValues['normThreads']
+    if dataValues['cpuType'] == "normalised":
+        if currentThreads is None:
+            print("Warning: Can't convert normalised CPU values, as the thread count they were normalised against is unknown.")
+            return
+        # convert back to absolute values first
+        dataValues['cv'] = [x * currentThreads for x in dataValues['cv']]
+
+    if targetCPUType == "normalised":
+        if targetThreads is None:
+            targetThreads = dataValues['sysThreads']
+        if targetThreads is None or targetThreads < 1:
+            print("Warning: Can't normalise CPU values, as the system thread count is unknown.")
+            # we've converted to absolute values above, so mark them as such
+            targetCPUType = "absolute"
+        else:
+            dataValues['cv'] = [x / targetThreads for x in dataValues['cv']]
+            dataValues['normThreads'] = targetThreads
+
+    dataValues['cpuType'] = targetCPUType
+    dataValues['mcv'] = max(dataValues['cv'])
+
+# draws vertical lines at the times the recorded process was switched to its replacement
+def drawPIDSwitchMarkers(axis, dataValues):
+    for switchTime in dataValues['pst']:
+        axis.axvline(x=switchTime, color='gray', linestyle='--', linewidth=1.0)
+
 def generateBasicCombinedPlot(dataValues, areaPlot, verticalGridLines):
     fig, ax1 = plt.subplots(1, 1)
 
@@ -147,6 +250,7 @@ def generateBasicCombinedPlot(dataValues, areaPlot, verticalGridLines):
     else:    
         ax1.plot(timeValues, dataValues['cv'], color='blue')
         ax2.plot(timeValues, dataValues['rv'], color='red')
+    drawPIDSwitchMarkers(ax1, dataValues)
 
     isCPUDataAbsolute = dataValues['cpuType'] == "absolute"
 
@@ -201,6 +305,7 @@ def generateBasicSeparatePlot(dataValues, areaPlot, verticalGridLines):
         axes[0].fill_between(timeValues, dataValues['cv'], color='blue', alpha=0.7)
     else:
         axes[0].plot(timeValues, dataValues['cv'], color='blue')
+    drawPIDSwitchMarkers(axes[0], dataValues)
     xLabel = "Time elapsed ({})".format("Minutes" if dataValues['tu'] == "m" else "Hours" if dataValues['tu'] == "h" else "Seconds")
 
     isCPUDataAbsolute = dataValues['cpuType'] == "absolute"
@@ -216,6 +321,7 @@ def generateBasicSeparatePlot(dataValues, areaPlot, verticalGridLines):
         axes[1].fill_between(timeValues, dataValues['rv'], color='red', alpha=0.7)
     else:
         axes[1].plot(timeValues, dataValues['rv'], color='red')
+    drawPIDSwitchMarkers(axes[1], dataValues)
     axes[1].set_xlabel(xLabel)
 
     rssYLabel = "Memory RSS ({})".format("MB" if dataValues['ru'] == "mb" else "GB")
@@ -231,6 +337,7 @@ def generateBasicSeparatePlot(dataValues, areaPlot, verticalGridLines):
             axes[2].fill_between(timeValues, dataValues['tcv'], color='green', alpha=0.7)
         else:
             axes[2].plot(timeValues, dataValues['tcv'], color='green')
+        drawPIDSwitchMarkers(axes[2], dataValues)
         axes[2].set_xlabel(xLabel)
 
         threadsYLabel = "Active Thread Count"
@@ -252,6 +359,85 @@ def generateBasicSeparatePlot(dataValues, areaPlot, verticalGridLines):
 
     plt.show()
 
+# reads the summary of a parameter sweep (from 'psrec bench --sweep'), returning None if the file isn't one
+def readSweepSummaryFromCSVFile(filename):
+    paramName = None
+    efficiencyThreads = None
+    columnNames = None
+    rows = []
+
+    fData = open(filename, "r")
+    for line in fData:
+        line = line.strip()
+        if len(line) == 0:
+            continue
+        if line.startswith("#@ "):
+            metadata_items = [x.strip() for x in line[3:].split(':', 1)]
+            if metadata_items[0] == "sweepparam":
+                paramName = metadata_items[1]
+            elif metadata_items[0] == "efficiencythreads":
+                efficiencyThreads = int(metadata_items[1])
+            continue
+        if line.startswith("# ") and ',' in line:
+            columnNames = [x.strip() for x in line[2:].split(',')]
+            continue
+        if line[0] == '#':
+            continue
+        rows.append(line.split(','))
+
+    if paramName is None or columnNames is None or len(rows) == 0:
+        return None
+
+    paramValues = [row[0] for row in rows]
+    # plot numeric values on a numeric axis, otherwise just in order
+    try:
+        paramPositions = [float(x) for x in paramValues]
+        numericParam = True
+    except ValueError:
+        paramPositions = list(range(len(paramValues)))
+        numericParam = False
+
+    def columnValues(name):
+        index = columnNames.index(name)
+        return [float(row[index]) if len(row[index]) > 0 else 0.0 for row in rows]
+
+    values = {'param':paramName, 'pv':paramValues, 'pp':paramPositions, 'numeric':numericParam,
+              'effThreads':efficiencyThreads,
+              'wall':columnValues("Wall Time Mean"), 'wallCI':columnValues("Wall Time CI"),
+              'rss':[x / 1024.0 / 1024.0 for x in columnValues("Peak RSS Mean")],
+              'rssCI':[x / 1024.0 / 1024.0 for x in columnValues("Peak RSS CI")],
+              'eff':columnValues("CPU Efficiency Mean"), 'effCI':columnValues("CPU Efficiency CI")}
+    return values
+
+def generateSweepPlot(sweepValues, verticalGridLines):
+    fig, axes = plt.subplots(3, 1)
+
+    fig.set_figwidth(15)
+    fig.set_figheight(8)
+    fig.suptitle('Parameter sweep ({}): wall time, peak RSS memory usage and CPU efficiency (mean and 95% CI)'.format(sweepValues['param']))
+
+    effLabel = "CPU efficiency (%)"
+    if sweepValues['effThreads'] is not None:
+        effLabel = "CPU efficiency (% of {} threads)".format(sweepValues['effThreads'])
+
+    plots = [('wall', 'wallCI', "Wall time (Seconds)", 'blue'),
+             ('rss', 'rssCI', "Peak RSS (MB)", 'red'),
+             ('eff', 'effCI', effLabel, 'green')]
+    for axis, (valuesKey, ciKey, yLabel, colour) in zip(axes, plots):
+        axis.errorbar(sweepValues['pp'], sweepValues[valuesKey], yerr=sweepValues[ciKey], color=colour, marker='o', capsize=4)
+        axis.set_xlabel(sweepValues['param'])
+        axis.set_ylabel(yLabel)
+        axis.set_ylim(ymin=0, ymax=None)
+        axis.yaxis.grid(color='lightgray')
+        if verticalGridLines:
+            axis.xaxis.grid(color='lightgray')
+        axis.set_xticks(sweepValues['pp'])
+        axis.set_xticklabels(sweepValues['pv'])
+
+    fig.tight_layout()
+
+    plt.show()
+
 if __name__ == '__main__':
     parser = argparse.ArgumentParser(
                     prog='psrec generate plot',
@@ -261,15 +447,29 @@ if __name__ == '__main__':
     parser.add_argument("--combined", action='store_true', help="Plot the recorded values in a combined single plot.")
     parser.add_argument("--areaplot", action='store_true', help="Plot the values as solid areas, rather than line plots.")
     parser.add_argument("--verticalgrid", action='store_true', help="Draw vertical grid lines for the Time axis.")
+    parser.add_argument("--cpuview", choices=["absolute", "normalised"], help="Plot the CPU usage values as absolute or normalised values, converting them if needed.")
+    parser.add_argument("--series", help="The name of the series to plot, for recordings of multiple processes. Defaults to the first series.")
+    parser.add_argument("--normthreads", type=int, help="The thread count to normalise the CPU usage values against (implies '--cpuview normalised').")
 
     args = parser.parse_args()
 
-    dataValues = readDataValuesFromCSVFile(args.inputFile)
+    # summaries of parameter sweeps are plotted against the parameter, rather than over time
+    sweepValues = readSweepSummaryFromCSVFile(args.inputFile)
+    if sweepValues:
+        generateSweepPlot(sweepValues, args.verticalgrid)
+        exit(0)
+
+    dataValues = readDataValuesFromCSVFile(args.inputFile, args.series)
 
     if not dataValues:
         print("Error: No valid recording data was found in the file specified to be plotted.");
         exit(-1)
 
+    if args.normthreads is not None:
+        convertCPUValues(dataValues, "normalised", args.normthreads)
+    elif args.cpuview is not None and args.cpuview != dataValues['cpuType']:
+        convertCPUValues(dataValues, args.cpuview, None)
+
     if args.combined:
         generateBasicCombinedPlot(dataValues, args.areaplot, args.verticalgrid)
     else:
@@ -0,0 +1,107 @@
+/*
+ psrec
+ Copyright 2022-2024 Peter Pearson.
+ Licensed under the Apache License, Version 2.0 (the "License");
+ You may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+ http://www.apache.org/licenses/LICENSE-2.0
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+ ---------
+*/
+
+use crate::process_samples::Sample;
+
+use std::time::Duration;
+
+// how long after the start of the recording to always sample at the minimum interval, to capture the startup phase
+const STARTUP_DURATION: Duration = Duration::from_secs(5);
+
+// changes between consecutive samples which are considered to be quick changes, that should be sampled at the
+// minimum interval: absolute CPU usage percentage points, and the fraction of the previous RSS.
+const CPU_CHANGE_THRESHOLD: f32 = 10.0;
+const RSS_CHANGE_THRESHOLD: f64 = 0.05;
+
+#[derive(Clone, Debug)]
+pub struct AdaptiveIntervalParams {
+    pub min_interval:       Duration,
+    pub max_interval:       Duration,
+}
+
+// Works out the interval until the next sample when sampling adaptively: the minimum interval is used during
+// the startup phase and whenever the CPU usage or RSS change quickly, and while they're steady the interval is
+// doubled after each sample, up to the maximum interval.
+// Intervals are always multiples of the minimum interval, and are returned as a number of minimum intervals.
+pub struct AdaptiveInterval {
+    // the maximum interval, as a number of minimum intervals
+    max_steps:          u64,
+
+    // the current interval, as a number of minimum intervals
+    steps:              u64,
+
+    last_cpu_usage:     Option<f32>,
+    last_rss:           Option<u64>,
+}
+
+impl AdaptiveInterval {
+    pub fn new(params: &AdaptiveIntervalParams) -> AdaptiveInterval {
+        let max_steps = (params.max_interval.as_nanos() / params.min_interval.as_nanos().max(1)).max(1) as u64;
+        AdaptiveInterval { max_steps, steps: 1, last_cpu_usage: None, last_rss: None }
+    }
+
+    // returns the number of minimum intervals until the next sample should be taken, given the latest (total) sample
+    // (if there was one) at the elapsed time since the start of the recording.
+    pub fn next_steps(&mut self, elapsed: Duration, sample: Option<&Sample>) -> u64 {
+        let mut changed_quickly = false;
+        if let Some(sample) = sample {
+            if let Some(last_cpu_usage) = self.last_cpu_usage {
+                changed_quickly |= (sample.cpu_usage - last_cpu_usage).abs() >= CPU_CHANGE_THRESHOLD;
+            }
+            if let Some(last_rss) = self.last_rss {
+                let rss_change = sample.curr_rss.abs_diff(last_rss) as f64;
+                changed_quickly |= rss_change >= last_rss.max(1) as f64 * RSS_CHANGE_THRESHOLD;
+            }
+
+            self.last_cpu_usage = Some(sample.cpu_usage);
+            self.last_rss = Some(sample.curr_rss);
+        }
+
+        if elapsed < STARTUP_DURATION || changed_quickly {
+            self.steps = 1;
+        }
+        else {
+            self.steps = (self.steps * 2).min(self.max_steps);
+        }
+
+        self.steps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adaptive_interval_backs_off_and_resets() {
+        let params = AdaptiveIntervalParams { min_interval: Duration::from_millis(50), max_interval: Duration::from_millis(300) };
+        let mut adaptive_interval = AdaptiveInterval::new(&params);
+
+        // always the minimum interval during startup
+        assert_eq!(adaptive_interval.next_steps(Duration::from_secs(1), Some(&Sample::for_test(0.0, 5.0, 1000))), 1);
+        assert_eq!(adaptive_interval.next_steps(Duration::from_secs(2), Some(&Sample::for_test(0.0, 5.0, 1000))), 1);
+
+        // steady state backs off, up to the maximum of 6 steps
+        assert_eq!(adaptive_interval.next_steps(Duration::from_secs(6), Some(&Sample::for_test(0.0, 6.0, 1010))), 2);
+        assert_eq!(adaptive_interval.next_steps(Duration::from_secs(7), Some(&Sample::for_test(0.0, 6.0, 1010))), 4);
+        assert_eq!(adaptive_interval.next_steps(Duration::from_secs(8), Some(&Sample::for_test(0.0, 6.0, 1010))), 6);
+        assert_eq!(adaptive_interval.next_steps(Duration::from_secs(9), None), 6);
+
+        // quick changes of either cpu usage or RSS go back to the minimum interval
+        assert_eq!(adaptive_interval.next_steps(Duration::from_secs(10), Some(&Sample::for_test(0.0, 50.0, 1010))), 1);
+        assert_eq!(adaptive_interval.next_steps(Duration::from_secs(11), Some(&Sample::for_test(0.0, 50.0, 1010))), 2);
+        assert_eq!(adaptive_interval.next_steps(Duration::from_secs(12), Some(&Sample::for_test(0.0, 50.0, 2000))), 1);
+    }
+}
@@ -0,0 +1,178 @@
+/*
+ psrec
+ Copyright 2022-2024 Peter Pearson.
+ Licensed under the Apache License, Version 2.0 (the "License");
+ You may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+ http://www.apache.org/licenses/LICENSE-2.0
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+ ---------
+*/
+
+use crate::cgroup::CgroupParams;
+use crate::launch_info::LaunchInfo;
+use crate::process_recorder::{ProcessRecordParams, ProcessRecorder, ProcessRecorderRun, RecordError};
+use crate::process_samples::ProcessRecording;
+use crate::stats::Stats;
+use crate::stop_conditions::StopReason;
+use crate::terminate_policy::{TerminateParams, TerminatePolicy};
+
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+#[derive(Clone, Debug)]
+pub struct BenchParams {
+    // number of runs to record and calculate the stats of
+    pub runs:           usize,
+
+    // number of runs before those which are discarded, i.e. to warm up file system caches
+    pub warmup_runs:    usize,
+
+    // if set, each run is started in its own cgroup
+    pub cgroup:         Option<CgroupParams>,
+}
+
+#[derive(Debug)]
+pub enum BenchError {
+    Record(RecordError),
+    // a run failed, with the exit status of the process
+    RunFailed(u8),
+    Cancelled,
+    // the recording of a run couldn't be exported
+    ExportFailed,
+}
+
+// the metrics of a single run, which the stats are calculated from
+#[derive(Clone, Copy, Debug)]
+pub struct BenchRunMetrics {
+    // in seconds
+    pub wall_time:      f64,
+    // in bytes
+    pub peak_rss:       u64,
+    // user + system CPU time, in seconds
+    pub cpu_time:       f64,
+    // mean absolute CPU usage over the wall time, i.e. one full thread is 100.0
+    pub mean_cpu:       f64,
+}
+
+impl BenchRunMetrics {
+    pub fn from_recording(recording: &ProcessRecording) -> Option<BenchRunMetrics> {
+        let exit_info = recording.exit_info.as_ref()?;
+        let wall_time = exit_info.wall_time?;
+
+        // the max RSS from wait4() is exact, but doesn't include child processes which are recorded in the samples
+        let max_sample_rss = recording.series.first().and_then(|series| series.samples.iter().map(|sample| sample.rss_range().1).max());
+        let max_rusage_rss = exit_info.resource_usage.as_ref().map(|rusage| rusage.max_rss);
+        let peak_rss = max_sample_rss.unwrap_or(0).max(max_rusage_rss.unwrap_or(0));
+
+        let cpu_time = exit_info.resource_usage.as_ref().map_or(0.0, |rusage| rusage.user_time + rusage.system_time);
+        let mean_cpu = if wall_time > 0.0 { 100.0 * cpu_time / wall_time } else { 0.0 };
+
+        Some(BenchRunMetrics { wall_time, peak_rss, cpu_time, mean_cpu })
+    }
+
+    pub fn description(&self) -> String {
+        format!("wall time: {:.3} secs, peak RSS: {} KB, CPU time: {:.3} secs, mean CPU: {:.1}%", self.wall_time,
+                self.peak_rss / 1024, self.cpu_time, self.mean_cpu)
+    }
+}
+
+// runs the process repeatedly, recording each run, and passes the recording of each (non-warmup) run, along with its
+// index, to the provided function as soon as the run has finished (i.e. to export it), so that nothing is lost if a
+// later run fails or the benchmark is cancelled. If the function returns false, the benchmark is stopped.
+pub fn run_benchmark(launch_info: &LaunchInfo, bench_params: &BenchParams, record_params: &ProcessRecordParams,
+                     cancel_flag: Arc<AtomicBool>, mut run_recorded: impl FnMut(usize, &ProcessRecording) -> bool) -> Result<(), BenchError> {
+    let total_runs = bench_params.warmup_runs + bench_params.runs;
+
+    for run_index in 0..total_runs {
+        let warmup = run_index < bench_params.warmup_runs;
+        if warmup {
+            eprintln!("Warmup run {}/{}...", run_index + 1, bench_params.warmup_runs);
+        }
+        else {
+            eprintln!("Run {}/{}...", run_index - bench_params.warmup_runs + 1, bench_params.runs);
+        }
+
+        let Some(mut recorder) = ProcessRecorderRun::new(launch_info.clone(), record_params) else {
+            return Err(BenchError::Record(RecordError::Other));
+        };
+        // don't leave processes running if the benchmark is cancelled
+        recorder.set_terminate_params(TerminateParams { policy: TerminatePolicy::Terminate, ..Default::default() });
+        if let Some(cgroup) = &bench_params.cgroup {
+            recorder.set_cgroup(cgroup.clone());
+        }
+        recorder.start(cancel_flag.clone()).map_err(BenchError::Record)?;
+
+        // Note: the cancel flag is reset while the process is being terminated, so it can't be checked here
+        let recording = recorder.get_recording();
+        if recording.stop_reason == Some(StopReason::Cancelled) {
+            eprintln!("Benchmark cancelled.");
+            return Err(BenchError::Cancelled);
+        }
+
+        let Some(exit_info) = &recording.exit_info else {
+            eprintln!("Error: the process was still running once the recording had stopped.");
+            return Err(BenchError::Record(RecordError::Other));
+        };
+        let exit_status = exit_info.shell_exit_status().unwrap_or(0);
+        if exit_status != 0 {
+            eprintln!("Error: the process {}, stopping the benchmark.", exit_info.description());
+            if let Some(oom_info) = &recording.oom_info {
+                oom_info.print_verdict();
+            }
+            if let Some(limit_hit) = &recording.limit_hit {
+                eprintln!("Recorded process {}.", limit_hit.description());
+            }
+            return Err(BenchError::RunFailed(exit_status));
+        }
+
+        if let Some(metrics) = BenchRunMetrics::from_recording(&recording) {
+            eprintln!("  {}", metrics.description());
+        }
+
+        if !warmup && !run_recorded(run_index - bench_params.warmup_runs, &recording) {
+            return Err(BenchError::ExportFailed);
+        }
+    }
+
+    Ok(())
+}
+
+// prints a table of the stats of each metric over the runs
+pub fn print_bench_summary(metrics: &[BenchRunMetrics], bench_params: &BenchParams) {
+    eprintln!("\nBenchmark results ({} runs, {} warmup runs):", metrics.len(), bench_params.warmup_runs);
+    eprintln!("  {:<16} {:>12} {:>12} {:>12} {:>12} {:>12}   95% CI", "", "mean", "median", "stddev", "min", "max");
+
+    let rows: [(&str, Vec<f64>, usize); 4] =
+        [("Wall time (s)", metrics.iter().map(|run| run.wall_time).collect(), 3),
+         ("Peak RSS (MB)", metrics.iter().map(|run| run.peak_rss as f64 / (1024.0 * 1024.0)).collect(), 2),
+         ("CPU time (s)", metrics.iter().map(|run| run.cpu_time).collect(), 3),
+         ("Mean CPU (%)", metrics.iter().map(|run| run.mean_cpu).collect(), 1)];
+    for (name, values, precision) in rows {
+        let Some(stats) = Stats::from_values(&values) else {
+            continue;
+        };
+        let ci = format!("{:.*} - {:.*}", precision, stats.ci95_low(), precision, stats.ci95_high());
+        eprintln!("  {:<16} {:>12.*} {:>12.*} {:>12.*} {:>12.*} {:>12.*}   {}", name, precision, stats.mean,
+                  precision, stats.median, precision, stats.stddev, precision, stats.min, precision, stats.max, ci);
+    }
+}
+
+// returns the path to export the recording of the run to, which is the export path with the run number appended to
+// the file name, i.e. results_run03.csv
+pub fn bench_run_export_path(export_path: &str, run_index: usize, runs: usize) -> String {
+    let path = Path::new(export_path);
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    let width = runs.to_string().len();
+    let mut file_name = format!("{}_run{:0width$}", stem, run_index + 1, width = width);
+    if let Some(extension) = path.extension() {
+        file_name.push('.');
+        file_name.push_str(&extension.to_string_lossy());
+    }
+    path.with_file_name(file_name).to_string_lossy().to_string()
+}
@@ -0,0 +1,375 @@
+/*
+ psrec
+ Copyright 2022-2024 Peter Pearson.
+ Licensed under the Apache License, Version 2.0 (the "License");
+ You may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+ http://www.apache.org/licenses/LICENSE-2.0
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+ ---------
+*/
+
+use crate::oom_monitor::read_key_value;
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Note: this only works on Linux with cgroups v2, and either running as root, or with a delegated cgroup subtree
+//       (i.e. with 'systemd-run --user --scope -p Delegate=yes').
+
+// the period cpu.max quotas are specified over, in microseconds
+const CPU_MAX_PERIOD: u64 = 100000;
+
+// how to set up the cgroup started processes are run in
+#[derive(Clone, Debug, Default)]
+pub struct CgroupParams {
+    // the cgroup to create the cgroup in (as shown in /proc/<pid>/cgroup), otherwise psrec's own cgroup is used
+    pub parent:         Option<String>,
+
+    // memory.max limit, in bytes
+    pub memory_max:     Option<u64>,
+
+    // cpu.max limit, as the number of CPUs worth of CPU time the processes can use
+    pub cpu_max:        Option<f64>,
+}
+
+// the totals of the cgroup, for all the processes which were in it
+#[derive(Clone, Debug, Default)]
+pub struct CgroupInfo {
+    pub path:               String,
+
+    pub memory_max:         Option<u64>,
+    pub cpu_max:            Option<f64>,
+
+    // in seconds
+    pub cpu_time:           Option<f64>,
+    pub user_time:          Option<f64>,
+    pub system_time:        Option<f64>,
+
+    // these depend on the memory/io controllers being enabled for the cgroup
+    pub memory_peak:        Option<u64>,
+    pub io_read_bytes:      Option<u64>,
+    pub io_write_bytes:     Option<u64>,
+}
+
+impl CgroupInfo {
+    pub fn print_summary(&self) {
+        eprintln!("Cgroup totals (all processes in: {}):", self.path);
+        if let Some(cpu_time) = self.cpu_time {
+            eprintln!("  CPU time:\t\t{:.3} secs ({:.3} user, {:.3} system)", cpu_time, self.user_time.unwrap_or(0.0),
+                        self.system_time.unwrap_or(0.0));
+        }
+        if let Some(memory_peak) = self.memory_peak {
+            eprintln!("  Peak memory:\t\t{} KB", memory_peak / 1024);
+        }
+        if let (Some(read_bytes), Some(write_bytes)) = (self.io_read_bytes, self.io_write_bytes) {
+            eprintln!("  I/O:\t\t\t{} KB read, {} KB written", read_bytes / 1024, write_bytes / 1024);
+        }
+    }
+
+    pub fn metadata_items(&self) -> Vec<(&'static str, String)> {
+        let mut items = vec![("cgroup", self.path.clone())];
+        if let Some(memory_max) = self.memory_max {
+            items.push(("cgroupmemorymax", memory_max.to_string()));
+        }
+        if let Some(cpu_max) = self.cpu_max {
+            items.push(("cgroupcpumax", cpu_max.to_string()));
+        }
+        if let Some(cpu_time) = self.cpu_time {
+            items.push(("cgroupcputime", format!("{:.3}", cpu_time)));
+        }
+        if let Some(user_time) = self.user_time {
+            items.push(("cgroupusertime", format!("{:.3}", user_time)));
+        }
+        if let Some(system_time) = self.system_time {
+            items.push(("cgroupsystemtime", format!("{:.3}", system_time)));
+        }
+        if let Some(memory_peak) = self.memory_peak {
+            items.push(("cgroupmempeak", memory_peak.to_string()));
+        }
+        if let Some(read_bytes) = self.io_read_bytes {
+            items.push(("cgroupioread", read_bytes.to_string()));
+        }
+        if let Some(write_bytes) = self.io_write_bytes {
+            items.push(("cgroupiowrite", write_bytes.to_string()));
+        }
+        items
+    }
+}
+
+// a cgroup created to run started processes in, so that all their descendants are accounted for, even if they
+// daemonise or are re-parented.
+pub struct Cgroup {
+    // the path of the cgroup within the cgroup hierarchy (as shown in /proc/<pid>/cgroup)
+    path:                   String,
+
+    // the directory of the cgroup in the file system
+    dir:                    PathBuf,
+
+    // the directory of the parent cgroup, and the controllers which were enabled for its children for this cgroup
+    parent_dir:             PathBuf,
+    enabled_controllers:    Vec<&'static str>,
+
+    params:                 CgroupParams,
+}
+
+impl Cgroup {
+    // creates a new cgroup for the current psrec process, and applies any limits to it. If it can't be, the process
+    // can still be recorded without it, unless limits or the parent cgroup were specified, so this is reported as an
+    // error or a warning depending on that.
+    pub fn create(params: &CgroupParams) -> Option<Cgroup> {
+        let report_failure = |message: &str| {
+            if params.is_required() {
+                eprintln!("Error setting up cgroup for the process: {}.", message);
+            }
+            else {
+                eprintln!("Warning: couldn't set up a cgroup for the process ({}), so only the process tree will be recorded.", message);
+            }
+        };
+
+        let Some(mount_point) = find_cgroup2_mount_point() else {
+            report_failure("cgroups v2 aren't available");
+            return None;
+        };
+
+        let parent_path = match &params.parent {
+            Some(parent_path) => parent_path.clone(),
+            None => {
+                let Some(parent_path) = find_cgroup2_path(std::process::id()) else {
+                    report_failure("couldn't find the current cgroup");
+                    return None;
+                };
+                parent_path
+            }
+        };
+        let parent_path = parent_path.trim_end_matches('/').to_string();
+        let parent_dir = PathBuf::from(format!("{}{}", mount_point, parent_path));
+        let parent_description = if parent_path.is_empty() { "/".to_string() } else { parent_path.clone() };
+
+        // only the controllers needed for the limits are enabled for the new cgroup (if they aren't already), as that
+        // changes the parent cgroup, which is undone when the cgroup is removed. Any other totals (i.e. the peak memory)
+        // are only available if the controllers for them have already been enabled (i.e. by delegation).
+        // Note: enabling controllers will fail if the parent cgroup has processes in it (other than the root one).
+        let mut required_controllers = Vec::new();
+        if params.memory_max.is_some() {
+            required_controllers.push("memory");
+        }
+        if params.cpu_max.is_some() {
+            required_controllers.push("cpu");
+        }
+        let available_controllers = std::fs::read_to_string(parent_dir.join("cgroup.controllers")).unwrap_or_default();
+        let subtree_control = std::fs::read_to_string(parent_dir.join("cgroup.subtree_control")).unwrap_or_default();
+        let mut enabled_controllers = Vec::new();
+        for controller in required_controllers {
+            if subtree_control.split_whitespace().any(|item| item == controller) {
+                continue;
+            }
+            if !available_controllers.split_whitespace().any(|item| item == controller) {
+                report_failure(&format!("the {} controller isn't available for cgroups in: '{}'", controller, parent_description));
+                disable_controllers(&parent_dir, &enabled_controllers);
+                return None;
+            }
+            if let Err(err) = std::fs::write(parent_dir.join("cgroup.subtree_control"), format!("+{}", controller)) {
+                report_failure(&format!("couldn't enable the {} controller for cgroups in: '{}', {}", controller, parent_description, err));
+                disable_controllers(&parent_dir, &enabled_controllers);
+                return None;
+            }
+            enabled_controllers.push(controller);
+        }
+
+        let path = format!("{}/psrec-{}", parent_path, std::process::id());
+        let dir = PathBuf::from(format!("{}{}", mount_point, path));
+        if let Err(err) = std::fs::create_dir(&dir) {
+            report_failure(&format!("couldn't create cgroup: '{}', {}", dir.display(), err));
+            disable_controllers(&parent_dir, &enabled_controllers);
+            return None;
+        }
+
+        let cgroup = Cgroup { path, dir, parent_dir, enabled_controllers, params: params.clone() };
+
+        let mut limits = Vec::new();
+        if let Some(memory_max) = params.memory_max {
+            limits.push(("memory.max", memory_max.to_string()));
+        }
+        if let Some(cpu_max) = params.cpu_max {
+            let quota = (cpu_max * CPU_MAX_PERIOD as f64).round().max(1000.0) as u64;
+            limits.push(("cpu.max", format!("{} {}", quota, CPU_MAX_PERIOD)));
+        }
+        for (name, value) in limits {
+            if let Err(err) = std::fs::write(cgroup.dir.join(name), &value) {
+                report_failure(&format!("couldn't set {} of cgroup to: '{}', {}", name, value, err));
+                cgroup.remove();
+                return None;
+            }
+        }
+
+        Some(cgroup)
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // sets up the command so that the process is started in the cgroup. Processes are moved into it between fork()
+    // and exec(), so that any processes it starts are also in it.
+    #[cfg(unix)]
+    pub fn add_to_command(&self, command: &mut Command) {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::process::CommandExt;
+
+        let procs_path = CString::new(self.dir.join("cgroup.procs").as_os_str().as_bytes()).unwrap();
+        // Safety: open(), write() and close() are async-signal-safe, so it's fine to call them between fork() and exec().
+        unsafe {
+            command.pre_exec(move || {
+                let fd = libc::open(procs_path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                if fd < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                // writing 0 moves the writing process itself
+                let result = libc::write(fd, b"0".as_ptr() as *const libc::c_void, 1);
+                let err = std::io::Error::last_os_error();
+                libc::close(fd);
+                if result < 0 {
+                    return Err(err);
+                }
+                Ok(())
+            });
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn add_to_command(&self, _command: &mut Command) {
+    }
+
+    // returns the PIDs of the processes currently in the cgroup
+    pub fn pids(&self) -> Vec<u32> {
+        read_pids(&self.dir)
+    }
+
+    // reads the totals of the cgroup
+    pub fn info(&self) -> CgroupInfo {
+        let mut info = CgroupInfo { path: self.path.clone(),
+                                    memory_max: self.params.memory_max,
+                                    cpu_max: self.params.cpu_max,
+                                    ..Default::default() };
+
+        if let Ok(cpu_stat) = std::fs::read_to_string(self.dir.join("cpu.stat")) {
+            let usec_to_secs = |usec: u64| usec as f64 / 1000000.0;
+            info.cpu_time = read_key_value(&cpu_stat, "usage_usec").map(usec_to_secs);
+            info.user_time = read_key_value(&cpu_stat, "user_usec").map(usec_to_secs);
+            info.system_time = read_key_value(&cpu_stat, "system_usec").map(usec_to_secs);
+        }
+
+        info.memory_peak = std::fs::read_to_string(self.dir.join("memory.peak")).ok()
+                                                            .and_then(|val| val.trim().parse::<u64>().ok());
+
+        if let Ok(io_stat) = std::fs::read_to_string(self.dir.join("io.stat")) {
+            let (read_bytes, write_bytes) = parse_io_stat(&io_stat);
+            info.io_read_bytes = Some(read_bytes);
+            info.io_write_bytes = Some(write_bytes);
+        }
+
+        info
+    }
+
+    // removes the cgroup if there are no processes left in it, otherwise leaves it, as they'd have to be moved
+    // elsewhere first.
+    pub fn remove(&self) {
+        let pids = self.pids();
+        if !pids.is_empty() {
+            eprintln!("Warning: {} process(es) still running in cgroup: '{}', so it hasn't been removed.", pids.len(), self.path);
+            return;
+        }
+
+        if let Err(err) = std::fs::remove_dir(&self.dir) {
+            eprintln!("Warning: couldn't remove cgroup: '{}', {}", self.dir.display(), err);
+            return;
+        }
+
+        disable_controllers(&self.parent_dir, &self.enabled_controllers);
+    }
+}
+
+impl CgroupParams {
+    // whether the process has to be started in a cgroup, rather than that just being preferred
+    pub fn is_required(&self) -> bool {
+        self.parent.is_some() || self.memory_max.is_some() || self.cpu_max.is_some()
+    }
+}
+
+// disables the controllers for the children of the cgroup directory again, after they were enabled for a cgroup
+fn disable_controllers(dir: &Path, controllers: &[&str]) {
+    for controller in controllers {
+        if let Err(err) = std::fs::write(dir.join("cgroup.subtree_control"), format!("-{}", controller)) {
+            eprintln!("Warning: couldn't disable the {} controller for cgroups in: '{}' again, {}", controller, dir.display(), err);
+        }
+    }
+}
+
+// returns the PIDs of the processes in the cgroup directory
+pub fn read_pids(dir: &Path) -> Vec<u32> {
+    std::fs::read_to_string(dir.join("cgroup.procs")).unwrap_or_default()
+                    .lines().filter_map(|line| line.trim().parse::<u32>().ok()).collect()
+}
+
+fn find_cgroup2_mount_point() -> Option<String> {
+    let mount_info = std::fs::read_to_string("/proc/self/mountinfo").ok()?;
+    for line in mount_info.lines() {
+        // lines are in the form: <mount ID> <parent ID> <major:minor> <root> <mount point> <options> [optional fields]
+        // - <fs type> <source> <super options>
+        let Some((mount_fields, fs_fields)) = line.split_once(" - ") else {
+            continue;
+        };
+        if fs_fields.split_whitespace().next() == Some("cgroup2") {
+            return mount_fields.split_whitespace().nth(4).map(|mount_point| mount_point.to_string());
+        }
+    }
+
+    None
+}
+
+// returns the path of the cgroup v2 cgroup of the process
+fn find_cgroup2_path(pid: u32) -> Option<String> {
+    let cgroups = std::fs::read_to_string(format!("/proc/{}/cgroup", pid)).ok()?;
+    cgroups.lines().find_map(|line| line.strip_prefix("0::").map(|path| path.to_string()))
+}
+
+// returns the total bytes read and written from the contents of an io.stat file, which has a line per device in the
+// form: <major:minor> rbytes=N wbytes=N rios=N wios=N ...
+fn parse_io_stat(contents: &str) -> (u64, u64) {
+    let mut read_bytes = 0;
+    let mut write_bytes = 0;
+    for item in contents.split_whitespace() {
+        if let Some((key, value)) = item.split_once('=') {
+            let value = value.parse::<u64>().unwrap_or(0);
+            match key {
+                "rbytes" => read_bytes += value,
+                "wbytes" => write_bytes += value,
+                _ => {},
+            }
+        }
+    }
+    (read_bytes, write_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_io_stat() {
+        let contents = "8:16 rbytes=1459200 wbytes=314773504 rios=192 wios=353 dbytes=0 dios=0\n\
+                        8:0 rbytes=90430464 wbytes=299008000 rios=8950 wios=1252 dbytes=50331648 dios=3021\n";
+        assert_eq!(parse_io_stat(contents), (1459200 + 90430464, 314773504 + 299008000));
+        assert_eq!(parse_io_stat(""), (0, 0));
+    }
+}
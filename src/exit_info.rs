@@ -0,0 +1,183 @@
+/*
+ psrec
+ Copyright 2022-2024 Peter Pearson.
+ Licensed under the Apache License, Version 2.0 (the "License");
+ You may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+ http://www.apache.org/licenses/LICENSE-2.0
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+ ---------
+*/
+
+// final resource usage totals of a started process, as returned by wait4() once it has exited.
+// These are exact totals, so unlike the samples don't depend on the sample interval.
+#[derive(Clone, Debug, Default)]
+pub struct ResourceUsage {
+    // in bytes
+    pub max_rss:                        u64,
+
+    // in seconds
+    pub user_time:                      f64,
+    pub system_time:                    f64,
+
+    pub block_input_ops:                u64,
+    pub block_output_ops:               u64,
+
+    pub voluntary_context_switches:     u64,
+    pub involuntary_context_switches:   u64,
+}
+
+// how a started process exited.
+#[derive(Clone, Debug, Default)]
+pub struct ExitInfo {
+    // set if the process exited normally
+    pub exit_code:          Option<i32>,
+
+    // set if the process was terminated by a signal
+    pub signal:             Option<i32>,
+
+    pub core_dumped:        bool,
+
+    // time from starting the process to it exiting, in seconds
+    pub wall_time:          Option<f64>,
+
+    pub resource_usage:     Option<ResourceUsage>,
+}
+
+impl ExitInfo {
+    #[cfg(unix)]
+    pub fn from_wait_status(status: libc::c_int, rusage: Option<&libc::rusage>) -> ExitInfo {
+        let mut exit_info = ExitInfo::default();
+
+        if libc::WIFEXITED(status) {
+            exit_info.exit_code = Some(libc::WEXITSTATUS(status));
+        }
+        else if libc::WIFSIGNALED(status) {
+            exit_info.signal = Some(libc::WTERMSIG(status));
+            exit_info.core_dumped = libc::WCOREDUMP(status);
+        }
+
+        if let Some(rusage) = rusage {
+            let timeval_to_secs = |tv: &libc::timeval| tv.tv_sec as f64 + tv.tv_usec as f64 / 1_000_000.0;
+
+            // Note: ru_maxrss is in KB on Linux (but bytes on macOS)
+            let max_rss_mult = if cfg!(target_os = "macos") { 1 } else { 1024 };
+
+            exit_info.resource_usage = Some(ResourceUsage { max_rss: rusage.ru_maxrss as u64 * max_rss_mult,
+                                                            user_time: timeval_to_secs(&rusage.ru_utime),
+                                                            system_time: timeval_to_secs(&rusage.ru_stime),
+                                                            block_input_ops: rusage.ru_inblock as u64,
+                                                            block_output_ops: rusage.ru_oublock as u64,
+                                                            voluntary_context_switches: rusage.ru_nvcsw as u64,
+                                                            involuntary_context_switches: rusage.ru_nivcsw as u64 });
+        }
+
+        exit_info
+    }
+
+    #[cfg(not(unix))]
+    pub fn from_exit_status(status: &std::process::ExitStatus) -> ExitInfo {
+        ExitInfo { exit_code: status.code(), ..Default::default() }
+    }
+
+    // returns a human-readable description of how the process exited
+    pub fn description(&self) -> String {
+        if let Some(exit_code) = self.exit_code {
+            format!("exited with code {}", exit_code)
+        }
+        else if let Some(signal) = self.signal {
+            format!("terminated by signal {} ({}){}", signal, signal_name(signal),
+                        if self.core_dumped { ", core dumped" } else { "" })
+        }
+        else {
+            "exited with an unknown status".to_string()
+        }
+    }
+
+    // the exit status a shell would report for the process: the exit code, or 128 + the signal number if it
+    // was terminated by a signal.
+    pub fn shell_exit_status(&self) -> Option<u8> {
+        if let Some(exit_code) = self.exit_code {
+            Some((exit_code & 0xff) as u8)
+        }
+        else {
+            self.signal.map(|signal| (128 + signal).min(255) as u8)
+        }
+    }
+
+    pub fn print_summary(&self) {
+        eprintln!("Process {}.", self.description());
+
+        if let Some(wall_time) = self.wall_time {
+            eprintln!("  Wall time:\t\t{:.3} secs", wall_time);
+        }
+
+        if let Some(rusage) = &self.resource_usage {
+            eprintln!("  Max RSS:\t\t{} KB", rusage.max_rss / 1024);
+            eprintln!("  User time:\t\t{:.3} secs", rusage.user_time);
+            eprintln!("  System time:\t\t{:.3} secs", rusage.system_time);
+            eprintln!("  Block I/O ops:\t{} in, {} out", rusage.block_input_ops, rusage.block_output_ops);
+            eprintln!("  Context switches:\t{} voluntary, {} involuntary", rusage.voluntary_context_switches, rusage.involuntary_context_switches);
+        }
+    }
+
+    // returns the info as key/value pairs, for file formats which can only store metadata as strings.
+    pub fn metadata_items(&self) -> Vec<(&'static str, String)> {
+        let mut items = Vec::new();
+        if let Some(exit_code) = self.exit_code {
+            items.push(("exitcode", exit_code.to_string()));
+        }
+        if let Some(signal) = self.signal {
+            items.push(("exitsignal", format!("{} ({})", signal, signal_name(signal))));
+            items.push(("coredumped", self.core_dumped.to_string()));
+        }
+        if let Some(wall_time) = self.wall_time {
+            items.push(("walltime", format!("{:.3}", wall_time)));
+        }
+        if let Some(rusage) = &self.resource_usage {
+            items.push(("maxrss", rusage.max_rss.to_string()));
+            items.push(("usertime", format!("{:.3}", rusage.user_time)));
+            items.push(("systemtime", format!("{:.3}", rusage.system_time)));
+            items.push(("blockinops", rusage.block_input_ops.to_string()));
+            items.push(("blockoutops", rusage.block_output_ops.to_string()));
+            items.push(("volctxswitches", rusage.voluntary_context_switches.to_string()));
+            items.push(("involctxswitches", rusage.involuntary_context_switches.to_string()));
+        }
+
+        items
+    }
+}
+
+// returns the name of the signal number
+#[cfg(unix)]
+pub fn signal_name(signal: i32) -> &'static str {
+    match signal {
+        libc::SIGHUP =>     "SIGHUP",
+        libc::SIGINT =>     "SIGINT",
+        libc::SIGQUIT =>    "SIGQUIT",
+        libc::SIGILL =>     "SIGILL",
+        libc::SIGTRAP =>    "SIGTRAP",
+        libc::SIGABRT =>    "SIGABRT",
+        libc::SIGBUS =>     "SIGBUS",
+        libc::SIGFPE =>     "SIGFPE",
+        libc::SIGKILL =>    "SIGKILL",
+        libc::SIGUSR1 =>    "SIGUSR1",
+        libc::SIGSEGV =>    "SIGSEGV",
+        libc::SIGUSR2 =>    "SIGUSR2",
+        libc::SIGPIPE =>    "SIGPIPE",
+        libc::SIGALRM =>    "SIGALRM",
+        libc::SIGTERM =>    "SIGTERM",
+        libc::SIGXCPU =>    "SIGXCPU",
+        libc::SIGXFSZ =>    "SIGXFSZ",
+        _ =>                "unknown",
+    }
+}
+
+#[cfg(not(unix))]
+pub fn signal_name(_signal: i32) -> &'static str {
+    "unknown"
+}
@@ -0,0 +1,111 @@
+/*
+ psrec
+ Copyright 2022-2024 Peter Pearson.
+ Licensed under the Apache License, Version 2.0 (the "License");
+ You may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+ http://www.apache.org/licenses/LICENSE-2.0
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+ ---------
+*/
+
+use crate::utils::quote_command_line_arg;
+
+// Information about the host machine and environment a recording was made on, so that recordings compared
+// later on can be tied back to the machine (and psrec invocation) that produced them.
+#[derive(Clone, Debug, Default)]
+pub struct HostInfo {
+    pub hostname:           String,
+
+    pub kernel_version:     String,
+
+    pub cpu_model:          String,
+
+    // number of physical CPU sockets/packages
+    pub cpu_sockets:        u32,
+    // number of physical CPU cores (across all sockets)
+    pub cpu_cores:          u32,
+    // number of logical CPU threads (across all sockets)
+    pub cpu_threads:        u32,
+
+    // in bytes
+    pub total_memory:       u64,
+
+    pub psrec_version:      String,
+
+    // the full command line psrec was invoked with
+    pub invocation:         String,
+}
+
+impl HostInfo {
+    pub fn gather() -> HostInfo {
+        let mut host_info = HostInfo { psrec_version: env!("CARGO_PKG_VERSION").to_string(),
+                                       invocation: std::env::args().map(|arg| quote_command_line_arg(&arg))
+                                                                   .collect::<Vec<String>>().join(" "),
+                                       ..Default::default() };
+
+        #[cfg(target_os = "linux")]
+        host_info.gather_linux();
+
+        // fall back to this if we don't know any better...
+        if host_info.cpu_threads == 0 {
+            if let Ok(nt) = std::thread::available_parallelism() {
+                host_info.cpu_threads = nt.get() as u32;
+            }
+        }
+
+        host_info
+    }
+
+    #[cfg(target_os = "linux")]
+    fn gather_linux(&mut self) {
+        if let Ok(hostname) = std::fs::read_to_string("/proc/sys/kernel/hostname") {
+            self.hostname = hostname.trim().to_string();
+        }
+        if let Ok(kernel_version) = std::fs::read_to_string("/proc/sys/kernel/osrelease") {
+            self.kernel_version = kernel_version.trim().to_string();
+        }
+
+        if let Ok(cpu_info) = procfs::CpuInfo::new() {
+            self.cpu_model = cpu_info.model_name(0).unwrap_or("").to_string();
+            self.cpu_threads = cpu_info.num_cores() as u32;
+
+            // work out the unique sockets and cores, as /proc/cpuinfo lists each logical CPU thread separately
+            let mut sockets = Vec::new();
+            let mut cores = Vec::new();
+            for cpu_num in 0..cpu_info.num_cores() {
+                let physical_id = cpu_info.physical_id(cpu_num).unwrap_or(0);
+                let core_id = cpu_info.get_field(cpu_num, "core id").and_then(|val| val.parse::<u32>().ok()).unwrap_or(cpu_num as u32);
+                if !sockets.contains(&physical_id) {
+                    sockets.push(physical_id);
+                }
+                if !cores.contains(&(physical_id, core_id)) {
+                    cores.push((physical_id, core_id));
+                }
+            }
+            self.cpu_sockets = sockets.len() as u32;
+            self.cpu_cores = cores.len() as u32;
+        }
+
+        if let Ok(mem_info) = procfs::Meminfo::new() {
+            self.total_memory = mem_info.mem_total;
+        }
+    }
+
+    // returns the info as key/value pairs, for file formats which can only store metadata as strings.
+    pub fn metadata_items(&self) -> Vec<(&'static str, String)> {
+        vec![("hostname", self.hostname.clone()),
+             ("kernel", self.kernel_version.clone()),
+             ("cpumodel", self.cpu_model.clone()),
+             ("cpusockets", self.cpu_sockets.to_string()),
+             ("cpucores", self.cpu_cores.to_string()),
+             ("cputhreads", self.cpu_threads.to_string()),
+             ("totalmem", self.total_memory.to_string()),
+             ("psrecversion", self.psrec_version.clone()),
+             ("invocation", self.invocation.clone())]
+    }
+}
@@ -0,0 +1,191 @@
+/*
+ psrec
+ Copyright 2022-2024 Peter Pearson.
+ Licensed under the Apache License, Version 2.0 (the "License");
+ You may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+ http://www.apache.org/licenses/LICENSE-2.0
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+ ---------
+*/
+
+use crate::resource_limits::ResourceLimits;
+use crate::target_info::{should_redact_env_var, REDACTED_ENV_VALUE};
+use crate::utils::quote_command_line_arg;
+
+use std::process::Command;
+
+// Details of how a started process was launched, so that it's possible to know afterwards exactly what was run
+// (the command line of the process itself might differ, i.e. when run via a shell), and in what environment.
+#[derive(Clone, Debug, Default)]
+pub struct LaunchInfo {
+    // the exact argv the process was started with
+    pub argv:           Vec<String>,
+
+    // whether the command was run through 'sh -c'
+    pub shell:          bool,
+
+    // environment variables set for the process (from the env file first, then individually specified ones)
+    pub env:            Vec<(String, String)>,
+
+    // the file the environment variables were read from, if any
+    pub env_file:       Option<String>,
+
+    // whether the process was started with an empty environment (apart from the variables above)
+    pub clear_env:      bool,
+
+    // working directory to start the process in, otherwise the current one is used
+    pub cwd:            Option<String>,
+
+    pub umask:          Option<u32>,
+
+    // setrlimit() limits to start the process with
+    pub limits:         ResourceLimits,
+}
+
+impl LaunchInfo {
+    pub fn new(argv: Vec<String>, shell: bool) -> LaunchInfo {
+        LaunchInfo { argv, shell, ..Default::default() }
+    }
+
+    // sets the environment variable for the process, replacing any previous value of it
+    pub fn set_env(&mut self, key: &str, value: &str) {
+        self.env.retain(|(existing_key, _)| existing_key != key);
+        self.env.push((key.to_string(), value.to_string()));
+    }
+
+    // builds the command to start the process with
+    pub fn command(&self) -> Command {
+        let mut command = Command::new(&self.argv[0]);
+        command.args(&self.argv[1..]);
+
+        if self.clear_env {
+            command.env_clear();
+        }
+        command.envs(self.env.iter().map(|(key, value)| (key, value)));
+
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
+        }
+
+        #[cfg(unix)]
+        if self.umask.is_some() || !self.limits.is_empty() {
+            use std::os::unix::process::CommandExt;
+
+            let umask = self.umask;
+            let limits = self.limits.clone();
+            // Safety: umask(), getrlimit() and setrlimit() are async-signal-safe, so it's fine to call them between
+            // fork() and exec().
+            unsafe {
+                command.pre_exec(move || {
+                    if let Some(umask) = umask {
+                        libc::umask(umask as libc::mode_t);
+                    }
+                    limits.apply()
+                });
+            }
+        }
+
+        #[cfg(not(unix))]
+        if self.umask.is_some() {
+            eprintln!("Warning: setting the umask of started processes is only supported on Unix platforms.");
+        }
+
+        command
+    }
+
+    // returns a copy of the info with the values of environment variables matching the redaction patterns redacted,
+    // for storing in recordings.
+    pub fn redacted(&self, redaction_patterns: &[String]) -> LaunchInfo {
+        let mut launch_info = self.clone();
+        for (key, value) in &mut launch_info.env {
+            if should_redact_env_var(key, redaction_patterns) {
+                *value = REDACTED_ENV_VALUE.to_string();
+            }
+        }
+        launch_info
+    }
+
+    // returns the argv as a single string, quoted as needed
+    pub fn argv_string(&self) -> String {
+        self.argv.iter().map(|arg| quote_command_line_arg(arg)).collect::<Vec<String>>().join(" ")
+    }
+
+    pub fn metadata_items(&self) -> Vec<(&'static str, String)> {
+        let mut items = vec![("launchargv", self.argv_string()),
+                             ("launchshell", self.shell.to_string())];
+        if let Some(env_file) = &self.env_file {
+            items.push(("launchenvfile", env_file.clone()));
+        }
+        if self.clear_env {
+            items.push(("launchclearenv", self.clear_env.to_string()));
+        }
+        for (key, value) in &self.env {
+            items.push(("launchenv", format!("{}={}", key, value)));
+        }
+        if let Some(cwd) = &self.cwd {
+            items.push(("launchcwd", cwd.clone()));
+        }
+        if let Some(umask) = self.umask {
+            items.push(("launchumask", format!("{:04o}", umask)));
+        }
+        items.extend(self.limits.metadata_items());
+        items
+    }
+}
+
+// parses an environment variable assignment in the form KEY=VALUE
+pub fn parse_env_assignment(assignment: &str) -> Option<(String, String)> {
+    let (key, value) = assignment.split_once('=')?;
+    if key.is_empty() || key.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((key.to_string(), value.to_string()))
+}
+
+// parses the contents of an env file: KEY=VALUE lines (optionally prefixed with 'export'), with blank lines and
+// lines starting with '#' ignored, and values optionally enclosed in quotes. Returns the line number of the first
+// invalid line on error.
+pub fn parse_env_file_contents(contents: &str) -> Result<Vec<(String, String)>, usize> {
+    let mut env = Vec::new();
+    for (line_index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+
+        let Some((key, value)) = parse_env_assignment(line) else {
+            return Err(line_index + 1);
+        };
+        let value = value.trim();
+        let value = if value.len() >= 2 && ((value.starts_with('"') && value.ends_with('"')) ||
+                                            (value.starts_with('\'') && value.ends_with('\''))) {
+            &value[1..value.len() - 1]
+        }
+        else {
+            value
+        };
+        env.push((key.trim().to_string(), value.to_string()));
+    }
+    Ok(env)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_env_file_contents() {
+        let contents = "# threading\nOMP_NUM_THREADS=4\n\nexport NAME=\"some value\"\nEMPTY=\nEQUALS='a=b'\n";
+        let env = parse_env_file_contents(contents).unwrap();
+        assert_eq!(env, vec![("OMP_NUM_THREADS".to_string(), "4".to_string()), ("NAME".to_string(), "some value".to_string()),
+                             ("EMPTY".to_string(), "".to_string()), ("EQUALS".to_string(), "a=b".to_string())]);
+
+        assert_eq!(parse_env_file_contents("A=1\nnot an assignment\n"), Err(2));
+    }
+}
@@ -13,23 +13,59 @@
  ---------
 */
 
+mod adaptive_interval;
+mod bench;
+mod cgroup;
+mod exit_info;
+mod host_info;
+mod launch_info;
+mod oom_monitor;
+mod output_capture;
+mod process_finder;
+mod process_follower;
 mod process_sampler;
 
 #[cfg(target_os = "linux")]
 mod process_sampler_advanced;
 
+mod process_sampler_cgroup;
+
 mod process_samples;
 mod process_recorder;
+mod resource_limits;
+mod sample_scheduler;
+mod sample_trigger;
+mod stats;
+mod stop_conditions;
+mod sweep;
+mod target_info;
+mod terminate_policy;
 mod utils;
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::sync::Arc;
 use std::sync::atomic::AtomicBool;
+use std::time::Duration;
 
 use argh::FromArgs;
+use chrono::Local;
 
+use crate::bench::{bench_run_export_path, print_bench_summary, run_benchmark, BenchError, BenchParams, BenchRunMetrics};
+use crate::cgroup::CgroupParams;
+use crate::host_info::HostInfo;
+use crate::launch_info::{parse_env_assignment, parse_env_file_contents, LaunchInfo};
+use crate::output_capture::OutputCaptureParams;
+use crate::process_finder::{find_matching_processes, select_processes, wait_for_matching_processes, ProcessPatterns, ProcessSelection};
+use crate::process_follower::{read_pid_file, FollowParams, FollowTarget};
 use crate::process_recorder::*;
-use crate::process_samples::ProcessRecording;
+use crate::process_samples::{ExportParams, ProcessRecording, SeriesLayout, TimestampFormat};
+use crate::resource_limits::{ResourceLimitKind, ResourceLimits};
+use crate::sample_trigger::{TriggerCondition, TriggerParams};
+use crate::stop_conditions::{StopCondition, StopReason};
+use crate::sweep::{print_sweep_summary, run_sweep, sweep_run_export_path, sweep_summary_path, SweepParam, SweepSummaryFile, SweepVariant};
+use crate::terminate_policy::{TerminateParams, TerminatePolicy};
+use crate::utils::{convert_size_string_to_bytes, convert_time_period_string_to_ms, parse_deadline_string, split_command_line};
 
 // TODO: this is pretty masochistic just to print a help banner/message formatted somewhat as I want it,
 //       it's probably worth using another command line parser crate which allows better flexibility,
@@ -40,8 +76,12 @@ Copyright 2022-2024 Peter Pearson.
 
 A utility to record information about a process' execution statistics, e.g. cpu and memory usage."#,
        example = r#"psrec -i 250ms -c -e /tmp/outfile1.csv attach <pid>
+psrec -i 1s --aggregate -e /tmp/outfile1.csv attach <pid1>=server <pid2> <pid3>
 psrec -i 5s -c -e /tmp/outfile2.csv start <command>
-psrec -i 250ms -c -e /tmp/outfile3.csv start "<command_to_run> [additional_arg1] [additional_arg2]""#)
+psrec -i 250ms -e /tmp/outfile2.csv wait --name <regex> --timeout 5m
+psrec -i 250ms -c -e /tmp/outfile3.csv start -- <command_to_run> [additional_arg1] [additional_arg2]
+psrec -i 250ms -c -e /tmp/outfile4.csv start --shell "<command_to_run> | <other_command>"
+psrec -i 100ms -e /tmp/outfile5.csv bench -n 10 --warmup 2 -- <command_to_run> [additional_arg1]"#)
 ]
 struct MainArgs {
     #[argh(subcommand)]
@@ -52,27 +92,108 @@ struct MainArgs {
     /// Not specifying a suffix unit char will use seconds.
     interval: Option<String>,
 
+    /// adapt the sample interval to how quickly the values are changing: sampling at the min interval during the
+    /// first seconds and whenever the cpu usage or RSS change quickly, and backing off to the max interval while steady.
+    #[argh(switch)]
+    adaptive_interval: bool,
+
+    /// the minimum (fastest) sample interval with --adaptive-interval, in various suffix units (ms/s/m/h).
+    /// Default is 50ms. Not specifying a suffix unit char will use seconds.
+    #[argh(option)]
+    min_interval: Option<String>,
+
+    /// the maximum (slowest) sample interval with --adaptive-interval, in various suffix units (ms/s/m/h).
+    /// Default is 5 seconds. Not specifying a suffix unit char will use seconds.
+    #[argh(option)]
+    max_interval: Option<String>,
+
     #[argh(option, short = 'd')]
     /// duration to record for in various suffix units (s/m/h). By default will be until the process being recorded ends.
     /// Not specifying a suffix unit char will use seconds.
     duration: Option<String>,
+
+    /// stop recording once the process has been idle (cpu usage at or below the idle cpu threshold) for the duration,
+    /// in various suffix units (ms/s/m/h). Not specifying a suffix unit char will use seconds.
+    #[argh(option)]
+    stop_when_idle: Option<String>,
+
+    /// cpu usage (absolute %, so 100.0 is one full thread) at or below which the process is considered idle for
+    /// --stop-when-idle. Default is 1.0.
+    #[argh(option)]
+    idle_cpu_threshold: Option<f32>,
+
+    /// stop recording once the RSS of the process exceeds the size, with optional K/M/G suffix units.
+    /// Not specifying a suffix unit char will use bytes.
+    #[argh(option)]
+    stop_rss_above: Option<String>,
+
+    /// the max number of samples to keep in memory for each process, after which older samples are progressively
+    /// downsampled (to min/max/mean buckets), with the most recent half always kept at full resolution.
+    #[argh(option)]
+    max_samples: Option<usize>,
+
+    /// trigger mode: only keep samples from when the cpu usage (absolute %, so 100.0 is one full thread) of the process
+    /// is above the threshold, along with the pre/post-trigger history around that.
+    #[argh(option)]
+    trigger_cpu_above: Option<f32>,
+
+    /// trigger mode: only keep samples from when the RSS of the process is above the size (with optional K/M/G suffix
+    /// units), along with the pre/post-trigger history around that.
+    #[argh(option)]
+    trigger_rss_above: Option<String>,
+
+    /// trigger mode: only keep samples from when the file at the path exists (it's deleted when it triggers, so it can be
+    /// created again to trigger again), along with the pre/post-trigger history around that.
+    #[argh(option)]
+    trigger_marker: Option<String>,
+
+    /// in trigger mode, how much history before a trigger to keep, in various suffix units (ms/s/m/h).
+    /// Default is 10 seconds. Not specifying a suffix unit char will use seconds.
+    #[argh(option)]
+    pre_trigger: Option<String>,
+
+    /// in trigger mode, how long to keep samples for after the trigger condition stops being met, in various
+    /// suffix units (ms/s/m/h). Default is 10 seconds. Not specifying a suffix unit char will use seconds.
+    #[argh(option)]
+    post_trigger: Option<String>,
+
+    /// stop recording at the (local) time: either a time of day ('HH:MM' or 'HH:MM:SS'), a date and time
+    /// ('YYYY-MM-DD HH:MM[:SS]') or an RFC 3339 timestamp.
+    #[argh(option)]
+    stop_at: Option<String>,
  
-    /// whether to use absolute (wallclock) times instead of time elapsed after start
-//    #[argh(switch)]
-//    absolute_timestamps: bool,
+    /// write an additional column of absolute (wallclock) timestamps for each sample to the exported file,
+    /// in the specified format: 'iso' (ISO 8601 date/time strings) or 'epoch' (seconds since the Unix epoch).
+    #[argh(option)]
+    absolute_timestamps: Option<String>,
+
+    /// whether to write timestamps (including the recording start time) in UTC, rather than the local timezone.
+    #[argh(switch)]
+    utc: bool,
 
-    /// whether to record cpu usage as 'normalised' values (out of 100%), instead of Absolute values (default).
+    /// number of decimal places to write the elapsed time of samples with. By default this depends on the sample
+    /// interval, e.g. 1 for intervals which are multiples of 100ms, 2 for multiples of 10ms, and 3 for smaller intervals.
+    #[argh(option)]
+    time_precision: Option<usize>,
+
+    /// whether to export (and print) cpu usage as 'normalised' values (out of 100%), instead of Absolute values (default).
     /// Absolute values will scale over 100.0 for the number of threads, so 800.0 will be 8 threads using full CPU.
     /// Normalised will be normalised to 100.0, so instead of 800.0 in the above example, it will be 100.0,
-    /// and 1 thread using 100% CPU will be 15.0% (assuming the computer has 8 cores/threads).
+    /// and 1 thread using 100% CPU will be 12.5% (assuming the computer has 8 cores/threads).
+    /// The recording itself always keeps absolute values, this only affects the exported/printed values.
     /// 
     /// Note: this will only work in the most basic of scenarios: i.e. where std::thread::available_parallelism()
     /// returns the number of threads the process being recorded will be running on. The normalised value will likely
     /// be incorrect in other more complex scenarios, e.g. running under cgroups environments that mask/limit the CPU
-    /// cores a process can run on for example.
+    /// cores a process can run on for example, in which case --normalise-threads can be used to override the count.
     #[argh(switch, short = 'n')]
     normalise_cpu_usage: bool,
 
+    /// thread count to normalise cpu usage values against (implies --normalise-cpu-usage), instead of the
+    /// thread count detected on the system.
+    #[argh(option)]
+    normalise_threads: Option<u32>,
+
     /// whether to record the data for child processes as well as the main process. Defaults to off (false).
     #[argh(switch, short = 'c')]
     record_child_processes: bool,
@@ -81,6 +202,32 @@ struct MainArgs {
     #[argh(switch, short = 't')]
     record_thread_count: bool,
 
+    /// whether to record the OOM score of the process (the 'badness' score the kernel OOM killer uses to pick processes
+    /// to kill), Linux only.
+    #[argh(switch)]
+    record_oom_score: bool,
+
+    /// whether to record the environment variables of the process being recorded in the recording metadata.
+    /// The values of variables with names matching any of the redaction patterns will be redacted.
+    #[argh(switch)]
+    record_environment: bool,
+
+    /// additional environment variable name pattern (matched case-insensitively against whole '_' separated parts of
+    /// the name, or as a suffix of it) to redact the values of when recording the environment. Can be specified
+    /// multiple times, and adds to the defaults of: PASSWORD, PASSWD, SECRET, TOKEN, KEY, CREDENTIAL, AUTH.
+    #[argh(option)]
+    redact_env: Vec<String>,
+
+    /// how to lay out recordings of multiple processes in the exported file: 'wide' (default, one row per sample time with
+    /// columns for each process) or 'long' (one row per sample of each process, with columns for the series name and PID).
+    #[argh(option)]
+    series_layout: Option<String>,
+
+    /// whether to export an additional 'total' series with the values of all the processes added together,
+    /// when recording multiple processes.
+    #[argh(switch)]
+    aggregate: bool,
+
     /// whether to print out values live as process is being recorded to stderr
     #[argh(switch)]
     print_values: bool,
@@ -93,24 +240,217 @@ struct MainArgs {
 
 #[derive(FromArgs, PartialEq, Debug)]
 #[argh(subcommand)]
+// the args are only parsed once, so the size of the start args doesn't matter
+#[allow(clippy::large_enum_variant)]
 enum SubCommandEnum {
     // Start the specified process with optional command line args
     Start(SubCommandStart),
     // Attach to a process based off the provided process ID
     Attach(SubCommandAttach),
+    // Wait for a process matching a pattern to start, and then attach to it
+    Wait(SubCommandWait),
+    // Start the specified process repeatedly, and report stats of the runs
+    Bench(SubCommandBench),
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
-/// Start a process with command line args.
+/// Start a process with command line args. Use '--' before the command to pass its args through exactly as is,
+/// i.e. 'start -- prog arg1 "arg two"'.
 #[argh(subcommand, name = "start")]
 struct SubCommandStart {
     #[argh(positional)]
-    /// command line command to start/run and record
+    /// command line command to start/run and record. If it's a single string with no other args (and isn't after
+    /// '--'), it's split into args with shell-like quoting rules.
+    command: String,
+
+    #[argh(positional, greedy)]
+    /// command line args
+    args: Vec<String>,
+
+    #[argh(switch)]
+    /// run the command (and any args, joined with spaces) through 'sh -c', so that pipes, redirections etc work.
+    shell: bool,
+
+    #[argh(option)]
+    /// environment variable to set for the process, in the form KEY=VALUE (can be specified multiple times).
+    env: Vec<String>,
+
+    #[argh(option)]
+    /// file of environment variables to set for the process, with one KEY=VALUE per line (variables specified
+    /// with --env take precedence).
+    env_file: Option<String>,
+
+    #[argh(switch)]
+    /// start the process with an empty environment, apart from the variables specified with --env/--env-file.
+    clear_env: bool,
+
+    #[argh(option)]
+    /// working directory to start the process in.
+    cwd: Option<String>,
+
+    #[argh(option)]
+    /// umask (in octal, i.e. 022) to start the process with.
+    umask: Option<String>,
+
+    #[argh(option)]
+    /// limit of the address space (virtual memory) size of the process (RLIMIT_AS), with an optional K/M/G suffix unit.
+    limit_as: Option<String>,
+
+    #[argh(option)]
+    /// limit of the data segment size of the process (RLIMIT_DATA), with an optional K/M/G suffix unit.
+    limit_data: Option<String>,
+
+    #[argh(option)]
+    /// limit of the CPU time of the process (RLIMIT_CPU), in various suffix units (ms/s/m/h), rounded up to whole
+    /// seconds. Not specifying a suffix unit char will use seconds.
+    limit_cpu: Option<String>,
+
+    #[argh(option)]
+    /// limit of the number of files the process can have open (RLIMIT_NOFILE).
+    limit_nofile: Option<u64>,
+
+    #[argh(option)]
+    /// limit of the size of core files of the process (RLIMIT_CORE), with an optional K/M/G suffix unit. 0 disables
+    /// core files.
+    limit_core: Option<String>,
+
+    #[argh(switch)]
+    /// start the process in its own cgroup (cgroups v2 only, needs to be root or have a delegated cgroup), so that all
+    /// its descendants are recorded as a whole, even if they daemonise or are re-parented.
+    cgroup: bool,
+
+    #[argh(option)]
+    /// the cgroup to create the process' cgroup in, i.e. a delegated one (implies --cgroup). By default psrec's own
+    /// cgroup is used.
+    cgroup_parent: Option<String>,
+
+    #[argh(option)]
+    /// memory.max limit of the process' cgroup, with an optional K/M/G suffix unit (implies --cgroup).
+    cgroup_memory_max: Option<String>,
+
+    #[argh(option)]
+    /// cpu.max limit of the process' cgroup, as the number of CPUs worth of CPU time it can use, i.e. 1.5 (implies
+    /// --cgroup).
+    cgroup_cpu_max: Option<f64>,
+
+    #[argh(switch)]
+    /// capture the stdout/stderr of the process, timestamping each line and storing them in the recording, while
+    /// still echoing them to the terminal.
+    capture_output: bool,
+
+    #[argh(option)]
+    /// file to write the captured (timestamped) stdout/stderr lines of the process to (implies --capture-output).
+    output_log: Option<String>,
+
+    #[argh(switch)]
+    /// don't echo the captured stdout/stderr of the process to the terminal (implies --capture-output).
+    no_echo_output: bool,
+
+    #[argh(option, default = "10000")]
+    /// the max number of captured lines of output to keep in the recording, beyond which the oldest lines are dropped
+    /// (the output log file still gets all of them). Default is 10000.
+    max_output_lines: usize,
+
+    #[argh(option)]
+    /// what to do with the process if the recording is stopped before it exits (i.e. Ctrl+C or the duration limit):
+    /// 'leave' it running (the default, unless capturing its output), 'forward' the signal to it, 'terminate' it (SIGTERM
+    /// to its process group, then SIGKILL after the grace period, and the default when capturing its output), or 'wait'
+    /// for it to exit.
+    on_stop: Option<String>,
+
+    #[argh(option)]
+    /// with '--on-stop terminate', how long to wait for the process to exit after SIGTERM before sending SIGKILL, in
+    /// various suffix units (ms/s/m/h). Default is 10 seconds. Not specifying a suffix unit char will use seconds.
+    grace_period: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Start a process repeatedly, recording each run, and report the mean/median/stddev/min/max and 95% confidence
+/// intervals of the wall time, peak RSS, CPU time and mean CPU usage of the runs. Each run's recording is exported
+/// with the run number appended to the export file name.
+#[argh(subcommand, name = "bench")]
+struct SubCommandBench {
+    #[argh(positional)]
+    /// command line command to run and record, as with 'start'.
     command: String,
 
     #[argh(positional, greedy)]
     /// command line args
     args: Vec<String>,
+
+    #[argh(option, short = 'n', default = "10")]
+    /// number of runs to record. Default is 10.
+    runs: usize,
+
+    #[argh(option, default = "0")]
+    /// number of runs to do (and discard) before the recorded runs, i.e. to warm up caches. Default is 0.
+    warmup: usize,
+
+    #[argh(switch)]
+    /// run the command (and any args, joined with spaces) through 'sh -c'.
+    shell: bool,
+
+    #[argh(option)]
+    /// environment variable to set for the process, in the form KEY=VALUE (can be specified multiple times).
+    env: Vec<String>,
+
+    #[argh(option)]
+    /// file of environment variables to set for the process, as with 'start'.
+    env_file: Option<String>,
+
+    #[argh(switch)]
+    /// start the process with an empty environment, apart from the variables specified with --env/--env-file.
+    clear_env: bool,
+
+    #[argh(option)]
+    /// working directory to start the process in.
+    cwd: Option<String>,
+
+    #[argh(option)]
+    /// umask (in octal, i.e. 022) to start the process with.
+    umask: Option<String>,
+
+    #[argh(option)]
+    /// limit of the address space size of the process (RLIMIT_AS), as with 'start'.
+    limit_as: Option<String>,
+
+    #[argh(option)]
+    /// limit of the data segment size of the process (RLIMIT_DATA), as with 'start'.
+    limit_data: Option<String>,
+
+    #[argh(option)]
+    /// limit of the CPU time of the process (RLIMIT_CPU), as with 'start'.
+    limit_cpu: Option<String>,
+
+    #[argh(option)]
+    /// limit of the number of files the process can have open (RLIMIT_NOFILE).
+    limit_nofile: Option<u64>,
+
+    #[argh(option)]
+    /// limit of the size of core files of the process (RLIMIT_CORE), as with 'start'.
+    limit_core: Option<String>,
+
+    #[argh(switch)]
+    /// start each run of the process in its own cgroup, as with 'start'.
+    cgroup: bool,
+
+    #[argh(option)]
+    /// the cgroup to create the process' cgroup in (implies --cgroup).
+    cgroup_parent: Option<String>,
+
+    #[argh(option)]
+    /// memory.max limit of the process' cgroup, with an optional K/M/G suffix unit (implies --cgroup).
+    cgroup_memory_max: Option<String>,
+
+    #[argh(option)]
+    /// cpu.max limit of the process' cgroup, as the number of CPUs worth of CPU time it can use (implies --cgroup).
+    cgroup_cpu_max: Option<f64>,
+
+    #[argh(option)]
+    /// parameter to sweep over, in the form NAME=VALUES, with the values being a comma-separated list of values
+    /// and/or integer ranges (START..END or START..END:STEP), i.e. 'THREADS=1,2,4,8'. The command is run for each
+    /// value, with '{{NAME}}' in the command and args replaced with it, and the NAME environment variable set to it.
+    sweep: Option<String>,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -118,21 +458,567 @@ struct SubCommandStart {
 #[argh(subcommand, name = "attach")]
 struct SubCommandAttach {
     #[argh(positional)]
-    /// PID of process to attach to and record
-    pid: u32,
+    /// PID of process to attach to and record. Multiple PIDs can be specified to record multiple processes on a common
+    /// timeline, each as a series named after the process, or with the name provided in the form PID=NAME.
+    pids: Vec<String>,
+
+    #[argh(option)]
+    /// regex pattern to find processes to attach to by matching against their names (as well as, or instead of
+    /// specifying PIDs). Note: on Linux process names are truncated to 15 chars.
+    name: Option<String>,
+
+    #[argh(option)]
+    /// regex pattern to find processes to attach to by matching against their full command lines.
+    cmdline: Option<String>,
+
+    #[argh(option)]
+    /// which processes to attach to when multiple processes match --name/--cmdline: 'newest', 'oldest',
+    /// 'all', or 'fail' (the default) to error.
+    select: Option<String>,
+
+    #[argh(option)]
+    /// path of a pid file to read the PID of the process to attach to from.
+    pidfile: Option<String>,
+
+    #[argh(switch)]
+    /// follow restarts of the process: when it exits, find its replacement and keep recording it in the same recording.
+    /// The replacement is found with the --pidfile if specified, otherwise the --name/--cmdline patterns if specified,
+    /// otherwise by looking for a process with exactly the same command line.
+    follow: bool,
+
+    #[argh(option)]
+    /// how long to wait for a replacement of the process when following restarts, before stopping the recording,
+    /// in various suffix units (ms/s/m/h). Default is 60 seconds. Not specifying a suffix unit char will use seconds.
+    follow_timeout: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Wait for a process matching a pattern to start, and then attach to it.
+#[argh(subcommand, name = "wait")]
+struct SubCommandWait {
+    #[argh(option)]
+    /// regex pattern to match against the names of processes to wait for.
+    /// Note: on Linux process names are truncated to 15 chars.
+    name: Option<String>,
+
+    #[argh(option)]
+    /// regex pattern to match against the full command lines of processes to wait for.
+    cmdline: Option<String>,
+
+    #[argh(option)]
+    /// which processes to attach to when multiple processes match at the same time: 'newest', 'oldest',
+    /// 'all', or 'fail' (the default) to error.
+    select: Option<String>,
+
+    #[argh(option)]
+    /// maximum time to wait for a matching process in various suffix units (ms/s/m/h). By default will wait indefinitely.
+    /// Not specifying a suffix unit char will use seconds.
+    timeout: Option<String>,
+
+    #[argh(option)]
+    /// interval between checks for a matching process in various suffix units (ms/s/m/h). Default is 100ms.
+    /// Not specifying a suffix unit char will use seconds.
+    poll_interval: Option<String>,
+
+    #[argh(switch)]
+    /// only wait for new processes, ignoring any matching processes which are already running.
+    new_only: bool,
+
+    #[argh(switch)]
+    /// follow restarts of the process: when it exits, find its replacement (the newest process matching the patterns)
+    /// and keep recording it in the same recording.
+    follow: bool,
+
+    #[argh(option)]
+    /// how long to wait for a replacement of the process when following restarts, before stopping the recording,
+    /// in various suffix units (ms/s/m/h). Default is 60 seconds. Not specifying a suffix unit char will use seconds.
+    follow_timeout: Option<String>,
+}
+
+// the args controlling how the process is launched, which are shared by the start and bench subcommands
+struct LaunchArgs<'a> {
+    shell:              bool,
+    env:                &'a [String],
+    env_file:           Option<&'a str>,
+    clear_env:          bool,
+    cwd:                Option<&'a str>,
+    umask:              Option<&'a str>,
+    limit_as:           Option<&'a str>,
+    limit_data:         Option<&'a str>,
+    limit_cpu:          Option<&'a str>,
+    limit_nofile:       Option<u64>,
+    limit_core:         Option<&'a str>,
+    cgroup:             bool,
+    cgroup_parent:      Option<&'a str>,
+    cgroup_memory_max:  Option<&'a str>,
+    cgroup_cpu_max:     Option<f64>,
+}
+
+impl SubCommandStart {
+    fn captures_output(&self) -> bool {
+        self.capture_output || self.output_log.is_some() || self.no_echo_output
+    }
+
+    fn launch_args(&self) -> LaunchArgs<'_> {
+        LaunchArgs { shell: self.shell, env: &self.env, env_file: self.env_file.as_deref(), clear_env: self.clear_env,
+                     cwd: self.cwd.as_deref(), umask: self.umask.as_deref(), limit_as: self.limit_as.as_deref(),
+                     limit_data: self.limit_data.as_deref(), limit_cpu: self.limit_cpu.as_deref(), limit_nofile: self.limit_nofile,
+                     limit_core: self.limit_core.as_deref(), cgroup: self.cgroup, cgroup_parent: self.cgroup_parent.as_deref(),
+                     cgroup_memory_max: self.cgroup_memory_max.as_deref(), cgroup_cpu_max: self.cgroup_cpu_max }
+    }
+}
+
+impl SubCommandBench {
+    fn launch_args(&self) -> LaunchArgs<'_> {
+        LaunchArgs { shell: self.shell, env: &self.env, env_file: self.env_file.as_deref(), clear_env: self.clear_env,
+                     cwd: self.cwd.as_deref(), umask: self.umask.as_deref(), limit_as: self.limit_as.as_deref(),
+                     limit_data: self.limit_data.as_deref(), limit_cpu: self.limit_cpu.as_deref(), limit_nofile: self.limit_nofile,
+                     limit_core: self.limit_core.as_deref(), cgroup: self.cgroup, cgroup_parent: self.cgroup_parent.as_deref(),
+                     cgroup_memory_max: self.cgroup_memory_max.as_deref(), cgroup_cpu_max: self.cgroup_cpu_max }
+    }
+}
+
+// works out how to launch the process (the environment, working directory etc) from the start/bench subcommand args
+fn start_launch_info(launch_args: &LaunchArgs, argv: Vec<String>) -> Option<LaunchInfo> {
+    let mut launch_info = LaunchInfo::new(argv, launch_args.shell);
+    launch_info.clear_env = launch_args.clear_env;
+
+    if let Some(env_file) = launch_args.env_file {
+        let contents = match std::fs::read_to_string(env_file) {
+            Ok(contents) => contents,
+            Err(err) => {
+                eprintln!("Error reading env file: '{}', {}", env_file, err);
+                return None;
+            }
+        };
+        match parse_env_file_contents(&contents) {
+            Ok(env) => {
+                for (key, value) in env {
+                    launch_info.set_env(&key, &value);
+                }
+            },
+            Err(line_number) => {
+                eprintln!("Error: invalid environment variable assignment on line {} of env file: '{}'.", line_number, env_file);
+                return None;
+            }
+        }
+        launch_info.env_file = Some(env_file.to_string());
+    }
+
+    for assignment in launch_args.env {
+        let Some((key, value)) = parse_env_assignment(assignment) else {
+            eprintln!("Error: invalid environment variable assignment: '{}'. It should be in the form KEY=VALUE.", assignment);
+            return None;
+        };
+        launch_info.set_env(&key, &value);
+    }
+
+    if let Some(cwd) = launch_args.cwd {
+        if !Path::new(cwd).is_dir() {
+            eprintln!("Error: working directory: '{}' does not exist.", cwd);
+            return None;
+        }
+        launch_info.cwd = Some(cwd.to_string());
+    }
+
+    if let Some(umask_string) = launch_args.umask {
+        match u32::from_str_radix(umask_string, 8) {
+            Ok(umask) if umask <= 0o777 => launch_info.umask = Some(umask),
+            _ => {
+                eprintln!("Error: invalid umask: '{}'. It should be an octal value, i.e. 022.", umask_string);
+                return None;
+            }
+        }
+    }
+
+    launch_info.limits = start_resource_limits(launch_args)?;
+
+    Some(launch_info)
+}
+
+// works out the resource limits to start the process with from the start/bench subcommand args
+fn start_resource_limits(launch_args: &LaunchArgs) -> Option<ResourceLimits> {
+    let mut limits = ResourceLimits::default();
+
+    let size_limits = [(ResourceLimitKind::AddressSpace, launch_args.limit_as),
+                       (ResourceLimitKind::Data, launch_args.limit_data),
+                       (ResourceLimitKind::CoreSize, launch_args.limit_core)];
+    for (kind, size_string) in size_limits {
+        if let Some(size_string) = size_string {
+            // a core size limit of 0 is valid (and the most common one)
+            let size = if kind == ResourceLimitKind::CoreSize && size_string.trim() == "0" { Some(0) }
+                       else { convert_size_string_to_bytes(size_string) };
+            let Some(size) = size else {
+                eprintln!("Error: invalid {} limit: '{}'.", kind.description(), size_string);
+                return None;
+            };
+            limits.set(kind, size);
+        }
+    }
+
+    if let Some(cpu_time_string) = launch_args.limit_cpu {
+        let Some((cpu_time_ms, _)) = convert_time_period_string_to_ms(cpu_time_string) else {
+            eprintln!("Error: invalid CPU time limit: '{}'.", cpu_time_string);
+            return None;
+        };
+        limits.set(ResourceLimitKind::CpuTime, cpu_time_ms.div_ceil(1000).max(1));
+    }
+
+    if let Some(open_files) = launch_args.limit_nofile {
+        limits.set(ResourceLimitKind::OpenFiles, open_files);
+    }
+
+    if !limits.check() {
+        return None;
+    }
+
+    Some(limits)
+}
+
+// works out the cgroup to start the process in (if any) from the start/bench subcommand args
+fn start_cgroup_params(launch_args: &LaunchArgs) -> Option<Option<CgroupParams>> {
+    if !launch_args.cgroup && launch_args.cgroup_parent.is_none() && launch_args.cgroup_memory_max.is_none() &&
+       launch_args.cgroup_cpu_max.is_none() {
+        return Some(None);
+    }
+
+    let mut cgroup_params = CgroupParams { parent: launch_args.cgroup_parent.map(str::to_string), ..Default::default() };
+
+    if let Some(memory_max_string) = launch_args.cgroup_memory_max {
+        let Some(memory_max) = convert_size_string_to_bytes(memory_max_string) else {
+            eprintln!("Error: invalid cgroup memory limit: '{}'.", memory_max_string);
+            return None;
+        };
+        cgroup_params.memory_max = Some(memory_max);
+    }
+
+    if let Some(cpu_max) = launch_args.cgroup_cpu_max {
+        if cpu_max <= 0.0 {
+            eprintln!("Error: invalid cgroup CPU limit: '{}'. It should be a positive number of CPUs.", cpu_max);
+            return None;
+        }
+        cgroup_params.cpu_max = Some(cpu_max);
+    }
+
+    Some(Some(cgroup_params))
+}
+
+// works out what to do with the process if the recording is stopped before it exits from the start subcommand args
+fn start_terminate_params(start: &SubCommandStart) -> Option<TerminateParams> {
+    let mut terminate_params = TerminateParams::default();
+    // a process whose output is being captured can't be left running, as its output pipes would be closed once
+    // psrec exits (so it'd get SIGPIPE or EPIPE errors)
+    if start.captures_output() {
+        terminate_params.policy = TerminatePolicy::Terminate;
+    }
+    if let Some(policy_string) = &start.on_stop {
+        let Some(policy) = TerminatePolicy::from_string(policy_string) else {
+            eprintln!("Error: unknown on-stop policy: '{}'. Supported policies are 'leave', 'forward', 'terminate' and 'wait'.", policy_string);
+            return None;
+        };
+        if policy == TerminatePolicy::Leave && start.captures_output() {
+            eprintln!("Error: the process can't be left running when capturing its output, as it would be writing to closed pipes.");
+            return None;
+        }
+        terminate_params.policy = policy;
+    }
+
+    if let Some(grace_period_string) = &start.grace_period {
+        let Some((grace_period_ms, _)) = convert_time_period_string_to_ms(grace_period_string) else {
+            eprintln!("Error: invalid grace period: '{}'.", grace_period_string);
+            return None;
+        };
+        if terminate_params.policy != TerminatePolicy::Terminate {
+            eprintln!("Warning: --grace-period has no effect without '--on-stop terminate'.");
+        }
+        terminate_params.grace_period = Duration::from_millis(grace_period_ms);
+    }
+
+    Some(terminate_params)
+}
+
+// runs the bench subcommand for each value of the sweep parameter, and reports the results of each value
+fn sweep_bench(launch_info: &LaunchInfo, sweep_param: &SweepParam, bench_params: &BenchParams, record_params: &ProcessRecordParams,
+               export_path: Option<&str>, export_params: &ExportParams, cancel_flag: Arc<AtomicBool>) -> ExitCode {
+    // CPU efficiency is relative to the same thread count CPU usage values are normalised against
+    let efficiency_thread_count = |variant: &SweepVariant| export_params.normalise_thread_count.unwrap_or(variant.num_system_threads);
+
+    // each run is exported as soon as it's finished, and the summary file is created once the first value has finished
+    // (when the thread count is known), with each value's results appended to it as they finish, so the results of the
+    // values which did finish are kept if a later one fails.
+    let mut summary_file: Option<SweepSummaryFile> = None;
+    let mut variants: Vec<SweepVariant> = Vec::with_capacity(sweep_param.values.len());
+    let result = run_sweep(launch_info, sweep_param, bench_params, record_params, cancel_flag, |value, run_index, recording| {
+        if let Some(export_path) = export_path {
+            let run_export_path = sweep_run_export_path(export_path, sweep_param, value, run_index, bench_params.runs);
+            if !recording.save_to_csv_file(&run_export_path, export_params) {
+                return false;
+            }
+            eprintln!("Saved results to file: {}", run_export_path);
+        }
+        true
+    }, |variant| {
+        variants.push(variant);
+        let variant = variants.last().unwrap();
+        if let Some(export_path) = export_path {
+            let summary_path = sweep_summary_path(export_path);
+            if summary_file.is_none() {
+                summary_file = SweepSummaryFile::create(&summary_path, sweep_param, efficiency_thread_count(variant));
+            }
+            if !summary_file.as_mut().is_some_and(|summary_file| summary_file.append_variant(variant)) {
+                return false;
+            }
+            eprintln!("Saved sweep summary to file: {}", summary_path);
+        }
+        true
+    });
+
+    // the results of the values which did finish are still printed if a later one failed (or it was cancelled)
+    if let Some(first_variant) = variants.first() {
+        print_sweep_summary(&variants, sweep_param, bench_params, efficiency_thread_count(first_variant));
+    }
+    if let Err(err) = result {
+        return ExitCode::from(bench_error_exit_code(err));
+    }
+
+    ExitCode::SUCCESS
+}
+
+// works out the exact argv to start the process with from the command and args of the start/bench subcommands.
+// The command is only split into args if it's a single string which wasn't passed through after '--'.
+fn command_argv(command: &str, args: &[String], shell: bool, passed_through: bool) -> Option<Vec<String>> {
+    if shell {
+        let mut shell_command = command.to_string();
+        for arg in args {
+            shell_command.push(' ');
+            shell_command.push_str(arg);
+        }
+        return Some(vec!["sh".to_string(), "-c".to_string(), shell_command]);
+    }
+
+    if !args.is_empty() || passed_through {
+        let mut argv = vec![command.to_string()];
+        argv.extend(args.iter().cloned());
+        return Some(argv);
+    }
+
+    // the command and its args have been provided as a single (quoted) string
+    match split_command_line(command) {
+        Ok(argv) => Some(argv),
+        Err(err) => {
+            eprintln!("Error: {} in command: {}", err.description(), command);
+            None
+        }
+    }
+}
+
+// returns whether the command (and its args) were passed after '--', which argh doesn't tell us itself.
+fn command_passed_through() -> bool {
+    std::env::args().any(|arg| arg == "--")
+}
+
+// parses the pre/post-trigger duration, which defaults to 10 seconds
+fn parse_trigger_duration(duration: Option<&str>, name: &str) -> Option<Duration> {
+    let Some(duration) = duration else {
+        return Some(Duration::from_secs(10));
+    };
+
+    if let Some((duration_ms, _)) = convert_time_period_string_to_ms(duration) {
+        Some(Duration::from_millis(duration_ms))
+    }
+    else {
+        eprintln!("Error: invalid {} duration: '{}'.", name, duration);
+        None
+    }
+}
+
+// returns the follow params for following restarts with the target, with the timeout string if specified
+fn follow_params(target: FollowTarget, timeout: Option<&str>) -> Option<FollowParams> {
+    let mut timeout_duration = Duration::from_secs(60);
+    if let Some(timeout_string) = timeout {
+        if let Some((timeout_ms, _)) = convert_time_period_string_to_ms(timeout_string) {
+            timeout_duration = Duration::from_millis(timeout_ms);
+        }
+        else {
+            eprintln!("Error: invalid follow timeout: '{}'.", timeout_string);
+            return None;
+        }
+    }
+
+    Some(FollowParams { target, timeout: timeout_duration })
+}
+
+// parses an attach target in the form 'PID' or 'PID=NAME'
+fn parse_attach_target(target: &str) -> Option<(u32, Option<String>)> {
+    let (pid_string, name) = match target.split_once('=') {
+        Some((pid_string, name)) => (pid_string, Some(name.trim().to_string())),
+        None =>                     (target, None),
+    };
+
+    // the names are used in the column names of the exported files, so need to be non-empty, and not contain commas
+    if name.as_ref().is_some_and(|name| name.is_empty() || name.contains(',')) {
+        return None;
+    }
+
+    Some((pid_string.trim().parse::<u32>().ok()?, name))
+}
+
+
+// parses the --select value for choosing which of multiple matching processes to attach to
+fn parse_process_selection(select: Option<&str>) -> Option<ProcessSelection> {
+    let Some(select) = select else {
+        return Some(ProcessSelection::Fail);
+    };
+
+    let selection = ProcessSelection::from_string(select);
+    if selection.is_none() {
+        eprintln!("Error: unknown process selection: '{}'. Supported values are 'newest', 'oldest', 'all' and 'fail'.", select);
+    }
+    selection
+}
+
+// exit codes for psrec's own failures. These are at the top of the range of exit codes (below those used for signals) to
+// avoid clashing with the exit codes of processes being started, which psrec otherwise exits with, and the ones for
+// errors running the command match the conventions of shells and tools like env.
+const EXIT_ATTACH_FAILED: u8 =              121;
+const EXIT_SAMPLER_INIT_FAILED: u8 =        122;
+const EXIT_EXPORT_FAILED: u8 =              123;
+const EXIT_ERROR: u8 =                      125;
+const EXIT_COMMAND_NOT_EXECUTABLE: u8 =     126;
+const EXIT_COMMAND_NOT_FOUND: u8 =          127;
+// cancelled (by Ctrl+C) before anything was recorded, as 128 + SIGINT
+const EXIT_CANCELLED: u8 =                  130;
+
+fn bench_error_exit_code(err: BenchError) -> u8 {
+    match err {
+        BenchError::Record(err) =>          record_error_exit_code(err),
+        BenchError::RunFailed(status) =>    status,
+        BenchError::Cancelled =>            EXIT_CANCELLED,
+        BenchError::ExportFailed =>         EXIT_EXPORT_FAILED,
+    }
+}
+
+fn record_error_exit_code(err: RecordError) -> u8 {
+    match err {
+        RecordError::AttachFailed =>            EXIT_ATTACH_FAILED,
+        RecordError::SamplerInitFailed =>       EXIT_SAMPLER_INIT_FAILED,
+        RecordError::CommandNotFound =>         EXIT_COMMAND_NOT_FOUND,
+        RecordError::CommandNotExecutable =>    EXIT_COMMAND_NOT_EXECUTABLE,
+        RecordError::Other =>                   EXIT_ERROR,
+    }
 }
 
+// attaches to and records the processes until they exit (or another stop condition is met), returning the recording.
+fn record_attached_processes(targets: &[(u32, Option<String>)], record_params: &ProcessRecordParams,
+                             cancel_flag: Arc<AtomicBool>) -> Result<ProcessRecording, RecordError> {
+    let pids: Vec<String> = targets.iter().map(|(pid, _)| pid.to_string()).collect();
+    eprintln!("Attaching to process PID: {}...", pids.join(", "));
+
+    let recorder: Option<ProcessRecorderAttach> = ProcessRecorderAttach::new(targets, record_params);
+    if recorder.is_none() {
+        eprintln!("Error attaching to process...");
+        return Err(RecordError::AttachFailed);
+    }
+
+    let mut recorder: ProcessRecorderAttach = recorder.unwrap();
+    // Note: start() prints some progress...
+    recorder.start(cancel_flag)?;
+
+    let recording = recorder.get_recording();
+
+    if recording.stop_reason == Some(StopReason::TargetExited) {
+        eprintln!("Attached process has exited.");
+        if let Some(oom_info) = &recording.oom_info {
+            oom_info.print_verdict();
+        }
+    }
+
+    Ok(recording)
+}
 
-fn main() {
+fn main() -> ExitCode {
     let args: MainArgs = argh::from_env();
 
+    let interval_specified = args.interval.is_some();
     let mut record_params = ProcessRecordParams::new(args.interval, args.duration);
+    record_params.set_host_info(HostInfo::gather());
 
     // TODO: something better than this... pass in args to ProcessRecordParams?
     //       or at least encapsulate it somewhere...
-    if args.normalise_cpu_usage {
-        record_params.set_normalise_cpu_usage(args.normalise_cpu_usage);
+    let mut export_params = ExportParams::new(true);
+
+    if args.normalise_cpu_usage || args.normalise_threads.is_some() {
+        record_params.set_normalise_cpu_usage(true);
+        export_params.normalise_cpu_usage = true;
+    }
+    if let Some(normalise_threads) = args.normalise_threads {
+        if normalise_threads == 0 {
+            eprintln!("Error: the thread count to normalise cpu usage values against must be greater than 0.");
+            return ExitCode::from(EXIT_ERROR);
+        }
+        record_params.set_normalise_thread_count(normalise_threads);
+        export_params.normalise_thread_count = Some(normalise_threads);
     }
+    if let Some(timestamp_format_string) = &args.absolute_timestamps {
+        if let Some(timestamp_format) = TimestampFormat::from_string(timestamp_format_string) {
+            export_params.timestamp_format = Some(timestamp_format);
+        }
+        else {
+            eprintln!("Error: unknown absolute timestamp format: '{}'. Supported formats are 'iso' and 'epoch'.", timestamp_format_string);
+            return ExitCode::from(EXIT_ERROR);
+        }
+    }
+    export_params.utc_timestamps = args.utc;
+    if let Some(series_layout_string) = &args.series_layout {
+        if let Some(series_layout) = SeriesLayout::from_string(series_layout_string) {
+            export_params.series_layout = series_layout;
+        }
+        else {
+            eprintln!("Error: unknown series layout: '{}'. Supported layouts are 'wide' and 'long'.", series_layout_string);
+            return ExitCode::from(EXIT_ERROR);
+        }
+    }
+    export_params.aggregate_series = args.aggregate;
+    if let Some(time_precision) = args.time_precision {
+        if time_precision > 9 {
+            eprintln!("Error: the time precision must be between 0 and 9 decimal places.");
+            return ExitCode::from(EXIT_ERROR);
+        }
+        export_params.time_precision = Some(time_precision);
+    }
+
+    if args.adaptive_interval {
+        if interval_specified {
+            eprintln!("Warning: --interval has no effect with --adaptive-interval, use --min-interval and --max-interval instead.");
+        }
+        let min_interval = args.min_interval.as_deref().unwrap_or("50ms");
+        let Some(min_interval) = convert_time_period_string_to_ms(min_interval) else {
+            eprintln!("Error: invalid min interval: '{}'.", min_interval);
+            return ExitCode::from(EXIT_ERROR);
+        };
+        let max_interval = args.max_interval.as_deref().unwrap_or("5s");
+        let Some(max_interval) = convert_time_period_string_to_ms(max_interval) else {
+            eprintln!("Error: invalid max interval: '{}'.", max_interval);
+            return ExitCode::from(EXIT_ERROR);
+        };
+        if max_interval.0 < min_interval.0 {
+            eprintln!("Error: the max interval must not be less than the min interval.");
+            return ExitCode::from(EXIT_ERROR);
+        }
+        record_params.set_adaptive_interval(min_interval, max_interval);
+    }
+    else if args.min_interval.is_some() || args.max_interval.is_some() {
+        eprintln!("Warning: --min-interval and --max-interval have no effect without --adaptive-interval.");
+    }
+
+    if let Some(max_samples) = args.max_samples {
+        if max_samples < 16 {
+            eprintln!("Error: the max number of samples must be at least 16.");
+            return ExitCode::from(EXIT_ERROR);
+        }
+        record_params.set_max_samples(max_samples);
+    }
+
     if args.print_values {
         record_params.set_print_values(true);
     }
@@ -142,6 +1028,76 @@ fn main() {
     if args.record_thread_count {
         record_params.set_record_thread_count(true);
     }
+    if args.record_oom_score {
+        record_params.set_record_oom_score(true);
+    }
+    if args.record_environment {
+        record_params.set_record_environment(true);
+    }
+    for pattern in &args.redact_env {
+        record_params.add_env_redaction_pattern(pattern);
+    }
+
+    if let Some(idle_duration_string) = &args.stop_when_idle {
+        if let Some((idle_duration, _)) = convert_time_period_string_to_ms(idle_duration_string) {
+            let cpu_threshold = args.idle_cpu_threshold.unwrap_or(1.0);
+            record_params.add_stop_condition(StopCondition::Idle { cpu_threshold, duration: Duration::from_millis(idle_duration) });
+        }
+        else {
+            eprintln!("Error: invalid idle duration: '{}'.", idle_duration_string);
+            return ExitCode::from(EXIT_ERROR);
+        }
+    }
+    else if args.idle_cpu_threshold.is_some() {
+        eprintln!("Warning: --idle-cpu-threshold has no effect without --stop-when-idle.");
+    }
+    if let Some(rss_limit_string) = &args.stop_rss_above {
+        if let Some(rss_limit) = convert_size_string_to_bytes(rss_limit_string) {
+            record_params.add_stop_condition(StopCondition::RssAbove(rss_limit));
+        }
+        else {
+            eprintln!("Error: invalid RSS size: '{}'.", rss_limit_string);
+            return ExitCode::from(EXIT_ERROR);
+        }
+    }
+    if let Some(deadline_string) = &args.stop_at {
+        if let Some(deadline) = parse_deadline_string(deadline_string, &Local::now()) {
+            record_params.add_stop_condition(StopCondition::Deadline(deadline));
+        }
+        else {
+            eprintln!("Error: invalid stop time: '{}'.", deadline_string);
+            return ExitCode::from(EXIT_ERROR);
+        }
+    }
+
+    let mut trigger_conditions = Vec::new();
+    if let Some(cpu_threshold) = args.trigger_cpu_above {
+        trigger_conditions.push(TriggerCondition::CpuAbove(cpu_threshold));
+    }
+    if let Some(rss_threshold_string) = &args.trigger_rss_above {
+        if let Some(rss_threshold) = convert_size_string_to_bytes(rss_threshold_string) {
+            trigger_conditions.push(TriggerCondition::RssAbove(rss_threshold));
+        }
+        else {
+            eprintln!("Error: invalid RSS size: '{}'.", rss_threshold_string);
+            return ExitCode::from(EXIT_ERROR);
+        }
+    }
+    if let Some(marker_path) = &args.trigger_marker {
+        trigger_conditions.push(TriggerCondition::MarkerFile(PathBuf::from(marker_path)));
+    }
+    if !trigger_conditions.is_empty() {
+        let Some(pre_trigger) = parse_trigger_duration(args.pre_trigger.as_deref(), "pre-trigger") else {
+            return ExitCode::from(EXIT_ERROR);
+        };
+        let Some(post_trigger) = parse_trigger_duration(args.post_trigger.as_deref(), "post-trigger") else {
+            return ExitCode::from(EXIT_ERROR);
+        };
+        record_params.set_trigger(TriggerParams { conditions: trigger_conditions, pre_trigger, post_trigger });
+    }
+    else if args.pre_trigger.is_some() || args.post_trigger.is_some() {
+        eprintln!("Warning: --pre-trigger and --post-trigger have no effect without a trigger condition.");
+    }
 
     // if we've been told to record results (not really sure we need it to be optional, but!)
     if let Some(export_path) = &args.export {
@@ -152,12 +1108,14 @@ fn main() {
             if !dir_path.exists() {
                 eprintln!("Error: directory path: '{}' for export path: '{}' does not seem to exist currently.",
                     dir_path.display(), export_path);
-                return;
+                return ExitCode::from(EXIT_ERROR);
             }
         }
     }
 
     let mut recording_results: Option<ProcessRecording> = None;
+    // in start mode, this is set to the exit status of the process
+    let mut exit_status = 0;
 
     // variable to allow interrupting with Ctrl+C handler...
     let has_been_cancelled_flag = Arc::new(AtomicBool::new(false));
@@ -175,83 +1133,308 @@ fn main() {
     }
 
     if let SubCommandEnum::Attach(attach) = args.command {
-        eprintln!("Attaching to process PID: {}...", attach.pid);
+        let mut targets: Vec<(u32, Option<String>)> = Vec::with_capacity(attach.pids.len());
+        for target_string in &attach.pids {
+            if let Some(target) = parse_attach_target(target_string) {
+                if targets.iter().any(|(pid, _)| *pid == target.0) {
+                    eprintln!("Error: PID: {} was specified more than once.", target.0);
+                    return ExitCode::from(EXIT_ERROR);
+                }
+                targets.push(target);
+            }
+            else {
+                eprintln!("Error: invalid process to attach to: '{}'. Expected a PID, or PID=NAME.", target_string);
+                return ExitCode::from(EXIT_ERROR);
+            }
+        }
+        if attach.name.is_some() || attach.cmdline.is_some() {
+            let Some(selection) = parse_process_selection(attach.select.as_deref()) else {
+                return ExitCode::from(EXIT_ERROR);
+            };
 
-        let recorder: Option<ProcessRecorderAttach> = ProcessRecorderAttach::new(attach.pid, &record_params);
-        if recorder.is_none() {
-            eprintln!("Error attaching to process...");
-            return;
+            let Some(patterns) = ProcessPatterns::new(attach.name.as_deref(), attach.cmdline.as_deref()) else {
+                return ExitCode::from(EXIT_ERROR);
+            };
+            eprintln!("Finding processes matching {}...", patterns.description());
+
+            let Some(found_processes) = select_processes(find_matching_processes(&patterns), selection) else {
+                return ExitCode::from(EXIT_ATTACH_FAILED);
+            };
+            for found_process in found_processes {
+                if !targets.iter().any(|(pid, _)| *pid == found_process.pid) {
+                    targets.push((found_process.pid, None));
+                }
+            }
+        }
+        else if attach.select.is_some() {
+            eprintln!("Warning: --select has no effect without --name or --cmdline.");
+        }
+        if let Some(pidfile) = &attach.pidfile {
+            let Some(pid) = read_pid_file(Path::new(pidfile)) else {
+                eprintln!("Error: could not read a PID from pid file: '{}'.", pidfile);
+                return ExitCode::from(EXIT_ATTACH_FAILED);
+            };
+            if !targets.iter().any(|(other_pid, _)| *other_pid == pid) {
+                targets.push((pid, None));
+            }
         }
 
-        let mut recorder: ProcessRecorderAttach = recorder.unwrap();
-        // Note: start() prints some progress...
-        if !recorder.start(has_been_cancelled_flag) {
-            // failed to create sampler, so error out...
-            return;
+        if targets.is_empty() {
+            eprintln!("Error: no PID of a process to attach to was specified.");
+            return ExitCode::from(EXIT_ERROR);
         }
 
-        recording_results = Some(recorder.get_recording());
+        if attach.follow {
+            if targets.len() > 1 {
+                eprintln!("Error: following restarts is only supported when recording a single process.");
+                return ExitCode::from(EXIT_ERROR);
+            }
 
-        eprintln!("Attached process has exited.");
+            let follow_target = if let Some(pidfile) = &attach.pidfile {
+                FollowTarget::PidFile(PathBuf::from(pidfile))
+            }
+            else if attach.name.is_some() || attach.cmdline.is_some() {
+                // these have already been checked to be valid above
+                FollowTarget::Patterns(ProcessPatterns::new(attach.name.as_deref(), attach.cmdline.as_deref()).unwrap())
+            }
+            else if let Some(patterns) = ProcessPatterns::same_command_as_process(targets[0].0) {
+                FollowTarget::Patterns(patterns)
+            }
+            else {
+                eprintln!("Error: could not get the command line of process PID: {} to follow restarts of.", targets[0].0);
+                return ExitCode::from(EXIT_ATTACH_FAILED);
+            };
+
+            let Some(follow) = follow_params(follow_target, attach.follow_timeout.as_deref()) else {
+                return ExitCode::from(EXIT_ERROR);
+            };
+            record_params.set_follow(follow);
+        }
+        else if attach.follow_timeout.is_some() {
+            eprintln!("Warning: --follow-timeout has no effect without --follow.");
+        }
+
+        match record_attached_processes(&targets, &record_params, has_been_cancelled_flag) {
+            Ok(recording) =>    recording_results = Some(recording),
+            Err(err) =>         return ExitCode::from(record_error_exit_code(err)),
+        }
     }
-    else if let SubCommandEnum::Start(start) = args.command {
-        // first of all, see if 'start.command' has spaces in: if so, it's almost certainly a quoted string command line
-        // arg, which currently seems to be the only to "pass through" args via the argh crate, as otherwise it attempts
-        // to interpret them.
-        let mut command = start.command.clone();
-        let mut args: Option<Vec<String>> = None;
-
-        // see if there's a space in the command...
-        if let Some(pos) = command.find(' ') {
-            // create temporary, so we can split_at()
-            let temp_command = command.clone();
-            // if so assume there are "bundled" sequential args we want to extract after the first space
-            let (split_command, remainder) = temp_command.split_at(pos);
-            command = split_command.to_string();
-            let remainder = remainder.trim_start();
-            if !remainder.is_empty() {
-                args = Some(remainder.split(' ').map(str::to_string).collect());
+    else if let SubCommandEnum::Wait(wait) = args.command {
+        let Some(selection) = parse_process_selection(wait.select.as_deref()) else {
+            return ExitCode::from(EXIT_ERROR);
+        };
+
+        if wait.name.is_none() && wait.cmdline.is_none() {
+            eprintln!("Error: --name and/or --cmdline must be specified to match the process to wait for.");
+            return ExitCode::from(EXIT_ERROR);
+        }
+        let Some(patterns) = ProcessPatterns::new(wait.name.as_deref(), wait.cmdline.as_deref()) else {
+            return ExitCode::from(EXIT_ERROR);
+        };
+
+        let mut poll_interval = Duration::from_millis(100);
+        if let Some(poll_interval_string) = &wait.poll_interval {
+            if let Some((poll_interval_ms, _)) = convert_time_period_string_to_ms(poll_interval_string) {
+                poll_interval = Duration::from_millis(poll_interval_ms);
+            }
+            else {
+                eprintln!("Error: invalid poll interval: '{}'.", poll_interval_string);
+                return ExitCode::from(EXIT_ERROR);
             }
+        }
 
-            // if there are further args in the command line args, append them afterwards
-            if !start.args.is_empty() {
-                if let Some(ref mut local_args) = &mut args {
-                    local_args.append(&mut start.args.clone());
-                }
-                else {
-                    args = Some(start.args.clone());
-                }
+        let mut timeout = None;
+        if let Some(timeout_string) = &wait.timeout {
+            if let Some((timeout_ms, _)) = convert_time_period_string_to_ms(timeout_string) {
+                timeout = Some(Duration::from_millis(timeout_ms));
+            }
+            else {
+                eprintln!("Error: invalid wait timeout: '{}'.", timeout_string);
+                return ExitCode::from(EXIT_ERROR);
             }
         }
 
-        eprintln!("Starting process: {}", command);
+        // processes which were already running aren't the ones we're waiting for if we're only after new ones
+        let ignored_pids: Vec<u32> = if wait.new_only {
+            find_matching_processes(&patterns).iter().map(|process| process.pid).collect()
+        }
+        else {
+            Vec::new()
+        };
 
-        let recorder: Option<ProcessRecorderRun> = ProcessRecorderRun::new(&command, args, &record_params);
+        eprintln!("Waiting for a process matching {}...", patterns.description());
+
+        let found_processes = wait_for_matching_processes(&patterns, &ignored_pids, poll_interval, timeout, &has_been_cancelled_flag);
+        if found_processes.is_empty() {
+            if has_been_cancelled_flag.load(std::sync::atomic::Ordering::SeqCst) {
+                eprintln!("Cancelled waiting for a matching process.");
+                return ExitCode::from(EXIT_CANCELLED);
+            }
+            eprintln!("Error: timed out waiting for a matching process.");
+            return ExitCode::from(EXIT_ATTACH_FAILED);
+        }
+
+        let Some(found_processes) = select_processes(found_processes, selection) else {
+            return ExitCode::from(EXIT_ATTACH_FAILED);
+        };
+        let targets: Vec<(u32, Option<String>)> = found_processes.iter().map(|process| (process.pid, None)).collect();
+
+        if wait.follow {
+            if targets.len() > 1 {
+                eprintln!("Error: following restarts is only supported when recording a single process.");
+                return ExitCode::from(EXIT_ERROR);
+            }
+
+            let Some(follow) = follow_params(FollowTarget::Patterns(patterns), wait.follow_timeout.as_deref()) else {
+                return ExitCode::from(EXIT_ERROR);
+            };
+            record_params.set_follow(follow);
+        }
+        else if wait.follow_timeout.is_some() {
+            eprintln!("Warning: --follow-timeout has no effect without --follow.");
+        }
+
+        match record_attached_processes(&targets, &record_params, has_been_cancelled_flag) {
+            Ok(recording) =>    recording_results = Some(recording),
+            Err(err) =>         return ExitCode::from(record_error_exit_code(err)),
+        }
+    }
+    else if let SubCommandEnum::Start(start) = args.command {
+        let Some(argv) = command_argv(&start.command, &start.args, start.shell, command_passed_through()) else {
+            return ExitCode::from(EXIT_ERROR);
+        };
+        let Some(launch_info) = start_launch_info(&start.launch_args(), argv) else {
+            return ExitCode::from(EXIT_ERROR);
+        };
+
+        eprintln!("Starting process: {}", launch_info.argv_string());
+
+        let recorder: Option<ProcessRecorderRun> = ProcessRecorderRun::new(launch_info, &record_params);
         if recorder.is_none() {
             // Note: this isn't actually that useful, as the process isn't actually started until start() is called...
             eprintln!("Error starting process...");
-            return;
+            return ExitCode::from(EXIT_ERROR);
         }
 
         let mut recorder: ProcessRecorderRun = recorder.unwrap();
+        let Some(terminate_params) = start_terminate_params(&start) else {
+            return ExitCode::from(EXIT_ERROR);
+        };
+        recorder.set_terminate_params(terminate_params);
+        let Some(cgroup_params) = start_cgroup_params(&start.launch_args()) else {
+            return ExitCode::from(EXIT_ERROR);
+        };
+        if let Some(cgroup_params) = cgroup_params {
+            recorder.set_cgroup(cgroup_params);
+        }
+        if start.captures_output() {
+            if start.max_output_lines == 0 {
+                eprintln!("Error: the max number of output lines must be at least 1.");
+                return ExitCode::from(EXIT_ERROR);
+            }
+            recorder.set_output_capture(OutputCaptureParams { echo: !start.no_echo_output, log_path: start.output_log.clone(),
+                                                              max_lines: start.max_output_lines });
+        }
         // Note: start() prints some progress...
-        if !recorder.start(has_been_cancelled_flag) {
-            // failed to start process, so return out
-            return;
+        if let Err(err) = recorder.start(has_been_cancelled_flag) {
+            return ExitCode::from(record_error_exit_code(err));
+        }
+
+        let recording = recorder.get_recording();
+
+        if recording.stop_reason == Some(StopReason::TargetExited) {
+            eprintln!("Recorded process has exited.");
         }
+        // the process might also have exited after the recording was stopped, depending on the terminate policy
+        if let Some(exit_info) = &recording.exit_info {
+            exit_info.print_summary();
+            if let Some(oom_info) = &recording.oom_info {
+                oom_info.print_verdict();
+            }
+            if let Some(limit_hit) = &recording.limit_hit {
+                eprintln!("Recorded process {}.", limit_hit.description());
+            }
+            if let Some(cgroup_info) = &recording.cgroup_info {
+                cgroup_info.print_summary();
+            }
 
-        recording_results = Some(recorder.get_recording());
+            // exit with the same status as the process, so wrapping commands in psrec doesn't hide failures
+            exit_status = exit_info.shell_exit_status().unwrap_or(0);
+        }
+        else if recording.stop_reason == Some(StopReason::TargetExited) {
+            // the process couldn't be waited for, so whether it failed isn't known
+            eprintln!("Error: the exit status of the recorded process couldn't be determined.");
+            exit_status = EXIT_ERROR;
+        }
+        // a cancelled recording shouldn't look like a success, whatever happened to the process
+        if recording.stop_reason == Some(StopReason::Cancelled) {
+            exit_status = EXIT_CANCELLED;
+        }
 
-        eprintln!("Recorded process has exited.");
+        recording_results = Some(recording);
+    }
+    else if let SubCommandEnum::Bench(bench) = args.command {
+        if bench.runs == 0 {
+            eprintln!("Error: the number of runs must be at least 1.");
+            return ExitCode::from(EXIT_ERROR);
+        }
+        let Some(argv) = command_argv(&bench.command, &bench.args, bench.shell, command_passed_through()) else {
+            return ExitCode::from(EXIT_ERROR);
+        };
+        let Some(launch_info) = start_launch_info(&bench.launch_args(), argv) else {
+            return ExitCode::from(EXIT_ERROR);
+        };
+        let Some(cgroup) = start_cgroup_params(&bench.launch_args()) else {
+            return ExitCode::from(EXIT_ERROR);
+        };
+        let bench_params = BenchParams { runs: bench.runs, warmup_runs: bench.warmup, cgroup };
+
+        if let Some(sweep_string) = &bench.sweep {
+            let Some(sweep_param) = SweepParam::parse(sweep_string) else {
+                eprintln!("Error: invalid sweep parameter: '{}'. It should be in the form NAME=VALUES, i.e. 'THREADS=1,2,4,8' or 'THREADS=1..8'.", sweep_string);
+                return ExitCode::from(EXIT_ERROR);
+            };
+            return sweep_bench(&launch_info, &sweep_param, &bench_params, &record_params, args.export.as_deref(),
+                               &export_params, has_been_cancelled_flag);
+        }
+
+        eprintln!("Benchmarking process: {}", launch_info.argv_string());
+
+        // each run is exported as soon as it's finished, so the runs which did finish are kept if a later one fails
+        let mut metrics: Vec<BenchRunMetrics> = Vec::with_capacity(bench_params.runs);
+        let result = run_benchmark(&launch_info, &bench_params, &record_params, has_been_cancelled_flag, |run_index, recording| {
+            metrics.extend(BenchRunMetrics::from_recording(recording));
+            if let Some(export_path) = &args.export {
+                let run_export_path = bench_run_export_path(export_path, run_index, bench_params.runs);
+                if !recording.save_to_csv_file(&run_export_path, &export_params) {
+                    return false;
+                }
+                eprintln!("Saved results to file: {}", run_export_path);
+            }
+            true
+        });
+
+        // the stats of the runs which did finish are still printed if a later one failed (or it was cancelled)
+        if !metrics.is_empty() {
+            print_bench_summary(&metrics, &bench_params);
+        }
+        if let Err(err) = result {
+            return ExitCode::from(bench_error_exit_code(err));
+        }
     }
 
     if let Some(export_path) = &args.export {
         if let Some(rec_results) = recording_results {
             // save the results
 
-            rec_results.save_to_csv_file(export_path, true);
-
-            eprintln!("Saved results to file: {}", export_path);
+            if rec_results.save_to_csv_file(export_path, &export_params) {
+                eprintln!("Saved results to file: {}", export_path);
+            }
+            else {
+                return ExitCode::from(EXIT_EXPORT_FAILED);
+            }
         }
     }
+
+    ExitCode::from(exit_status)
 }
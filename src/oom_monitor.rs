@@ -0,0 +1,258 @@
+/*
+ psrec
+ Copyright 2022-2024 Peter Pearson.
+ Licensed under the Apache License, Version 2.0 (the "License");
+ You may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+ http://www.apache.org/licenses/LICENSE-2.0
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+ ---------
+*/
+
+// Note: this only does anything useful on Linux, as it relies on the /proc and /sys/fs/cgroup file systems,
+//       but as it only reads files (which won't exist elsewhere), it doesn't need to be Linux-only.
+
+// the conclusion about whether the process was killed by the kernel OOM killer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OomVerdict {
+    // the process was killed with SIGKILL and the OOM kill counter of its cgroup went up
+    Killed,
+    // it looks like it was, but we can't be sure (i.e. we don't know the signal, or only the system-wide counter went up)
+    LikelyKilled,
+    NotKilled,
+}
+
+impl OomVerdict {
+    pub fn description(&self) -> &'static str {
+        match self {
+            OomVerdict::Killed =>         "killed by OOM",
+            OomVerdict::LikelyKilled =>   "likely killed by OOM",
+            OomVerdict::NotKilled =>      "not killed by OOM",
+        }
+    }
+}
+
+// the OOM-related details of the recorded process, with the event counts being the number of events which
+// happened while recording.
+#[derive(Clone, Debug)]
+pub struct OomInfo {
+    pub verdict:                OomVerdict,
+
+    // the memory cgroup of the process (if known)
+    pub cgroup_path:            Option<String>,
+
+    // OOM kills within the process' cgroup
+    pub cgroup_oom_kills:       Option<u64>,
+    // OOM kills on the whole system
+    pub system_oom_kills:       Option<u64>,
+
+    // number of times the memory usage of the cgroup hit its memory.max/memory.high limits (cgroups v2 only)
+    pub memory_max_events:      Option<u64>,
+    pub memory_high_events:     Option<u64>,
+
+    pub max_oom_score:          Option<i32>,
+}
+
+impl OomInfo {
+    // prints whether the process was (likely) killed by the OOM killer, and whether its cgroup hit its memory limit
+    pub fn print_verdict(&self) {
+        match self.verdict {
+            OomVerdict::Killed =>         eprintln!("Recorded process was killed by the kernel OOM killer."),
+            OomVerdict::LikelyKilled =>   eprintln!("Recorded process was likely killed by the kernel OOM killer."),
+            OomVerdict::NotKilled =>      {},
+        }
+        if let Some(count) = self.memory_max_events.filter(|count| *count > 0) {
+            eprintln!("Memory usage of the process' cgroup hit its memory.max limit {} times while recording.", count);
+        }
+    }
+
+    pub fn metadata_items(&self) -> Vec<(&'static str, String)> {
+        let mut items = vec![("oomverdict", self.verdict.description().to_string())];
+        if let Some(cgroup_path) = &self.cgroup_path {
+            items.push(("memcgroup", cgroup_path.clone()));
+        }
+        if let Some(count) = self.cgroup_oom_kills {
+            items.push(("cgroupoomkills", count.to_string()));
+        }
+        if let Some(count) = self.system_oom_kills {
+            items.push(("systemoomkills", count.to_string()));
+        }
+        if let Some(count) = self.memory_max_events {
+            items.push(("memorymaxevents", count.to_string()));
+        }
+        if let Some(count) = self.memory_high_events {
+            items.push(("memoryhighevents", count.to_string()));
+        }
+        if let Some(score) = self.max_oom_score {
+            items.push(("maxoomscore", score.to_string()));
+        }
+
+        items
+    }
+}
+
+// counters read from the cgroup memory events file
+#[derive(Clone, Copy, Debug, Default)]
+struct MemoryEventCounts {
+    oom_kill:   Option<u64>,
+    max:        Option<u64>,
+    high:       Option<u64>,
+}
+
+// keeps track of the OOM-related state of a process while it's being recorded.
+// Note: the cgroup counters are only read when monitoring starts and once the process has exited, so the only thing
+//       read for each sample is the OOM score, and only if it's being recorded.
+pub struct OomMonitor {
+    pid:                    u32,
+
+    cgroup_path:            Option<String>,
+    // either memory.events (cgroups v2), or memory.oom_control (cgroups v1)
+    memory_events_path:     Option<String>,
+
+    initial_events:         MemoryEventCounts,
+
+    initial_system_oom_kills:   Option<u64>,
+
+    record_oom_score:       bool,
+    max_oom_score:          Option<i32>,
+}
+
+impl OomMonitor {
+    pub fn new(pid: u32, record_oom_score: bool) -> OomMonitor {
+        let (cgroup_path, memory_events_path) = match find_memory_cgroup(pid) {
+            Some((cgroup_path, memory_events_path)) => (Some(cgroup_path), Some(memory_events_path)),
+            None =>                                    (None, None),
+        };
+
+        let initial_events = memory_events_path.as_ref().map(|path| read_memory_event_counts(path)).unwrap_or_default();
+
+        OomMonitor { pid,
+                     cgroup_path,
+                     memory_events_path,
+                     initial_events,
+                     initial_system_oom_kills: read_system_oom_kill_count(),
+                     record_oom_score,
+                     max_oom_score: None }
+    }
+
+    // called for each sample to keep track of the OOM score of the process, if it's being recorded.
+    // Returns the current OOM score.
+    pub fn update(&mut self) -> Option<i32> {
+        if !self.record_oom_score {
+            return None;
+        }
+
+        let oom_score = std::fs::read_to_string(format!("/proc/{}/oom_score", self.pid)).ok()
+                                                                .and_then(|val| val.trim().parse::<i32>().ok());
+        if let Some(score) = oom_score {
+            self.max_oom_score = Some(self.max_oom_score.map_or(score, |max_score| max_score.max(score)));
+        }
+
+        oom_score
+    }
+
+    // works out whether the process was killed by the OOM killer once it has exited. The termination signal should be
+    // provided if known (i.e. for processes we started), otherwise it's assumed it could have been SIGKILL.
+    pub fn finish(&mut self, exit_signal: Option<i32>, signal_known: bool) -> OomInfo {
+        // Note: if the cgroup doesn't exist anymore, the counts will be unknown
+        let final_events = self.memory_events_path.as_ref().map(|path| read_memory_event_counts(path)).unwrap_or_default();
+
+        let cgroup_oom_kills = count_delta(self.initial_events.oom_kill, final_events.oom_kill);
+        let system_oom_kills = count_delta(self.initial_system_oom_kills, read_system_oom_kill_count());
+
+        let could_be_oom_kill = if signal_known { exit_signal == Some(SIGKILL) } else { true };
+
+        let verdict = if !could_be_oom_kill {
+            OomVerdict::NotKilled
+        }
+        else if cgroup_oom_kills.unwrap_or(0) > 0 {
+            if signal_known { OomVerdict::Killed } else { OomVerdict::LikelyKilled }
+        }
+        else if signal_known && system_oom_kills.unwrap_or(0) > 0 {
+            // we can only go on the system-wide counter, so we can't be sure it was this process
+            OomVerdict::LikelyKilled
+        }
+        else {
+            OomVerdict::NotKilled
+        };
+
+        OomInfo { verdict,
+                  cgroup_path: self.cgroup_path.clone(),
+                  cgroup_oom_kills,
+                  system_oom_kills,
+                  memory_max_events: count_delta(self.initial_events.max, final_events.max),
+                  memory_high_events: count_delta(self.initial_events.high, final_events.high),
+                  max_oom_score: self.max_oom_score }
+    }
+}
+
+fn count_delta(initial: Option<u64>, last: Option<u64>) -> Option<u64> {
+    Some(last?.saturating_sub(initial?))
+}
+
+#[cfg(unix)]
+const SIGKILL: i32 = libc::SIGKILL;
+#[cfg(not(unix))]
+const SIGKILL: i32 = 9;
+
+// returns the path of the memory cgroup of the process, and the path of the file containing the OOM kill counter for it.
+fn find_memory_cgroup(pid: u32) -> Option<(String, String)> {
+    let cgroups = std::fs::read_to_string(format!("/proc/{}/cgroup", pid)).ok()?;
+
+    for line in cgroups.lines() {
+        // lines are in the form: <hierarchy ID>:<controllers>:<path>
+        let items: Vec<&str> = line.splitn(3, ':').collect();
+        if items.len() != 3 {
+            continue;
+        }
+
+        if items[0] == "0" && items[1].is_empty() {
+            // cgroups v2
+            let events_path = format!("/sys/fs/cgroup{}/memory.events", items[2].trim_end_matches('/'));
+            if std::path::Path::new(&events_path).exists() {
+                return Some((items[2].to_string(), events_path));
+            }
+        }
+        else if items[1].split(',').any(|controller| controller == "memory") {
+            // cgroups v1
+            let oom_control_path = format!("/sys/fs/cgroup/memory{}/memory.oom_control", items[2].trim_end_matches('/'));
+            if std::path::Path::new(&oom_control_path).exists() {
+                return Some((items[2].to_string(), oom_control_path));
+            }
+        }
+    }
+
+    None
+}
+
+// reads the value of a key from the contents of a file with "key value" lines
+pub fn read_key_value(contents: &str, key: &str) -> Option<u64> {
+    for line in contents.lines() {
+        if let Some((line_key, value)) = line.split_once(' ') {
+            if line_key == key {
+                return value.trim().parse::<u64>().ok();
+            }
+        }
+    }
+
+    None
+}
+
+fn read_memory_event_counts(path: &str) -> MemoryEventCounts {
+    if let Ok(contents) = std::fs::read_to_string(path) {
+        return MemoryEventCounts { oom_kill: read_key_value(&contents, "oom_kill"),
+                                   max: read_key_value(&contents, "max"),
+                                   high: read_key_value(&contents, "high") };
+    }
+
+    MemoryEventCounts::default()
+}
+
+fn read_system_oom_kill_count() -> Option<u64> {
+    let vmstat = std::fs::read_to_string("/proc/vmstat").ok()?;
+    read_key_value(&vmstat, "oom_kill")
+}
@@ -0,0 +1,185 @@
+/*
+ psrec
+ Copyright 2022-2024 Peter Pearson.
+ Licensed under the Apache License, Version 2.0 (the "License");
+ You may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+ http://www.apache.org/licenses/LICENSE-2.0
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+ ---------
+*/
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+// how long to wait for the reader threads to finish once the process has exited, as any processes it started which
+// are still running (i.e. daemons) might keep the pipes open.
+const FINISH_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+impl OutputStream {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputStream::Stdout => "stdout",
+            OutputStream::Stderr => "stderr",
+        }
+    }
+}
+
+// a line of output from the started process, with the elapsed time (in seconds, on the same time base as the samples)
+// it was read at.
+#[derive(Clone, Debug)]
+pub struct OutputLine {
+    pub elapsed_time:   f64,
+    pub stream:         OutputStream,
+    pub text:           String,
+}
+
+impl OutputLine {
+    // the value written to the recording metadata: "<time>,<stream>,<text>"
+    pub fn metadata_value(&self, time_precision: usize) -> String {
+        format!("{:.*},{},{}", time_precision, self.elapsed_time, self.stream.name(), self.text)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct OutputCaptureParams {
+    // whether to echo the output to the terminal (to psrec's own stdout/stderr) as it's read
+    pub echo:           bool,
+
+    // file to also write the timestamped output lines to as they're read
+    pub log_path:       Option<String>,
+
+    // the max number of lines to keep in the recording, so that the memory use is bounded. Beyond that the oldest
+    // lines are dropped (although the log file still gets all of them).
+    pub max_lines:      usize,
+}
+
+// the most recent lines captured, along with the number of older ones which have been dropped
+#[derive(Default)]
+struct CapturedLines {
+    lines:              VecDeque<OutputLine>,
+    dropped_count:      u64,
+}
+
+// Captures the stdout and stderr of a started process line by line (on a thread per stream), timestamping each line,
+// and teeing them to the terminal and/or a log file.
+pub struct OutputCapture {
+    params:             OutputCaptureParams,
+
+    log_file:           Option<Arc<Mutex<BufWriter<File>>>>,
+
+    lines:              Arc<Mutex<CapturedLines>>,
+
+    threads:            Vec<JoinHandle<()>>,
+}
+
+impl OutputCapture {
+    // Note: the log file (if any) is created here, before the process is started, so any error can be reported first.
+    pub fn new(params: OutputCaptureParams) -> Option<OutputCapture> {
+        let mut log_file = None;
+        if let Some(log_path) = &params.log_path {
+            match File::create(log_path) {
+                Ok(file) => log_file = Some(Arc::new(Mutex::new(BufWriter::new(file)))),
+                Err(err) => {
+                    eprintln!("Error creating output log file: '{}', {}", log_path, err);
+                    return None;
+                }
+            }
+        }
+
+        Some(OutputCapture { params, log_file, lines: Arc::new(Mutex::new(CapturedLines::default())), threads: Vec::new() })
+    }
+
+    // starts capturing the output from the stream, timestamping lines relative to the start time.
+    pub fn capture(&mut self, reader: impl Read + Send + 'static, stream: OutputStream, start_time: Instant) {
+        let echo = self.params.echo;
+        let max_lines = self.params.max_lines;
+        let log_file = self.log_file.clone();
+        let lines = self.lines.clone();
+
+        let thread = std::thread::spawn(move || {
+            let mut reader = BufReader::new(reader);
+            let mut buffer = Vec::new();
+            loop {
+                buffer.clear();
+                match reader.read_until(b'\n', &mut buffer) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {}
+                }
+                let elapsed_time = start_time.elapsed().as_secs_f64();
+
+                // the output might not be valid UTF-8, but we only need it to be readable
+                let text = String::from_utf8_lossy(&buffer).trim_end_matches(['\n', '\r']).to_string();
+
+                if echo {
+                    match stream {
+                        OutputStream::Stdout => println!("{}", text),
+                        OutputStream::Stderr => eprintln!("{}", text),
+                    }
+                }
+                if let Some(log_file) = &log_file {
+                    let mut log_file = log_file.lock().unwrap();
+                    let _ = writeln!(log_file, "{:.3}\t{}\t{}", elapsed_time, stream.name(), text);
+                }
+
+                let mut lines = lines.lock().unwrap();
+                if lines.lines.len() >= max_lines {
+                    lines.lines.pop_front();
+                    lines.dropped_count += 1;
+                }
+                lines.lines.push_back(OutputLine { elapsed_time, stream, text });
+            }
+        });
+
+        self.threads.push(thread);
+    }
+
+    // waits (for a limited time) for the output to be fully read once the process has exited, and returns the lines
+    // captured (that were kept), in time order, along with the number of older lines which were dropped.
+    pub fn finish(self) -> (Vec<OutputLine>, u64) {
+        let wait_start = Instant::now();
+        while !self.threads.iter().all(|thread| thread.is_finished()) && wait_start.elapsed() < FINISH_TIMEOUT {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        if let Some(log_file) = &self.log_file {
+            let _ = log_file.lock().unwrap().flush();
+        }
+
+        let captured_lines = self.lines.lock().unwrap();
+        let mut lines: Vec<OutputLine> = captured_lines.lines.iter().cloned().collect();
+        lines.sort_by(|line1, line2| line1.elapsed_time.total_cmp(&line2.elapsed_time));
+        (lines, captured_lines.dropped_count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_max_lines() {
+        let mut output_capture = OutputCapture::new(OutputCaptureParams { echo: false, log_path: None, max_lines: 2 }).unwrap();
+        output_capture.capture(std::io::Cursor::new("first\nsecond\r\nthird"), OutputStream::Stdout, Instant::now());
+
+        // only the most recent lines are kept
+        let (lines, dropped_count) = output_capture.finish();
+        let texts: Vec<&str> = lines.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(texts, vec!["second", "third"]);
+        assert_eq!(dropped_count, 1);
+    }
+}
@@ -0,0 +1,251 @@
+/*
+ psrec
+ Copyright 2022-2024 Peter Pearson.
+ Licensed under the Apache License, Version 2.0 (the "License");
+ You may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+ http://www.apache.org/licenses/LICENSE-2.0
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+ ---------
+*/
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use psutil::process::Process;
+use regex_lite::Regex;
+
+// which of the matching processes to record when there are multiple of them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProcessSelection {
+    // the most recently started one
+    Newest,
+    // the one which was started first
+    Oldest,
+    // all of them, as separate series
+    All,
+    // fail if there's more than one, as it's ambiguous which one is wanted
+    Fail,
+}
+
+impl ProcessSelection {
+    pub fn from_string(str_val: &str) -> Option<ProcessSelection> {
+        match str_val.to_ascii_lowercase().as_str() {
+            "newest" =>     Some(ProcessSelection::Newest),
+            "oldest" =>     Some(ProcessSelection::Oldest),
+            "all" =>        Some(ProcessSelection::All),
+            "fail" =>       Some(ProcessSelection::Fail),
+            _ =>            None,
+        }
+    }
+}
+
+// a process which matched the patterns
+#[derive(Clone, Debug)]
+pub struct FoundProcess {
+    pub pid:            u32,
+    pub name:           String,
+    pub cmdline:        String,
+
+    // since the Unix epoch, for ordering the processes
+    pub create_time:    f64,
+}
+
+// regex patterns to find processes with, which all have to match (unanchored) for a process to match.
+#[derive(Clone, Debug)]
+pub struct ProcessPatterns {
+    // matched against the process name
+    // Note: on Linux this is the 'comm' value of the process, which is truncated to 15 chars.
+    name:           Option<Regex>,
+
+    // matched against the full command line, with the args separated by spaces
+    cmdline:        Option<Regex>,
+}
+
+impl ProcessPatterns {
+    pub fn new(name_pattern: Option<&str>, cmdline_pattern: Option<&str>) -> Option<ProcessPatterns> {
+        let name = Self::compile_pattern(name_pattern)?;
+        let cmdline = Self::compile_pattern(cmdline_pattern)?;
+
+        Some(ProcessPatterns { name, cmdline })
+    }
+
+    // returns None if the pattern is invalid, or Some(None) if there is no pattern
+    fn compile_pattern(pattern: Option<&str>) -> Option<Option<Regex>> {
+        let Some(pattern) = pattern else {
+            return Some(None);
+        };
+
+        match Regex::new(pattern) {
+            Ok(regex) => Some(Some(regex)),
+            Err(err) => {
+                eprintln!("Error: invalid process pattern: '{}': {}", pattern, err);
+                None
+            }
+        }
+    }
+
+    // returns patterns matching processes with exactly the same command line as the process (or the same name,
+    // if it doesn't have a command line, i.e. kernel threads).
+    pub fn same_command_as_process(pid: u32) -> Option<ProcessPatterns> {
+        let process = Process::new(pid).ok()?;
+        let cmdline = process.cmdline().ok().flatten().unwrap_or_default();
+        if cmdline.is_empty() {
+            let name_pattern = format!("^{}$", regex_lite::escape(&process.name().ok()?));
+            return ProcessPatterns::new(Some(&name_pattern), None);
+        }
+
+        let cmdline_pattern = format!("^{}$", regex_lite::escape(&cmdline));
+        ProcessPatterns::new(None, Some(&cmdline_pattern))
+    }
+
+    // Note: Option::is_none_or() needs a much newer Rust version than we otherwise require.
+    #[allow(clippy::unnecessary_map_or)]
+    pub fn is_match(&self, name: &str, cmdline: &str) -> bool {
+        self.name.as_ref().map_or(true, |regex| regex.is_match(name)) &&
+            self.cmdline.as_ref().map_or(true, |regex| regex.is_match(cmdline))
+    }
+
+    // returns a description of the patterns, for printing
+    pub fn description(&self) -> String {
+        let mut descriptions = Vec::new();
+        if let Some(name) = &self.name {
+            descriptions.push(format!("name: '{}'", name.as_str()));
+        }
+        if let Some(cmdline) = &self.cmdline {
+            descriptions.push(format!("command line: '{}'", cmdline.as_str()));
+        }
+        descriptions.join(", ")
+    }
+}
+
+// returns all the running processes matching the patterns, apart from psrec itself and the processes which started it
+// (i.e. the shell running it), as the patterns will likely be in the command lines of those as well.
+pub fn find_matching_processes(patterns: &ProcessPatterns) -> Vec<FoundProcess> {
+    let processes = psutil::process::processes();
+    if let Err(err) = processes {
+        eprintln!("Error listing processes: {}", err);
+        return Vec::new();
+    }
+
+    let excluded_pids = own_process_ancestry();
+
+    let mut found_processes = Vec::new();
+    for process in processes.unwrap().into_iter().flatten() {
+        if excluded_pids.contains(&process.pid()) {
+            continue;
+        }
+
+        // processes can exit while we're looking at them, so just skip any we can't get the details of
+        let Ok(name) = process.name() else {
+            continue;
+        };
+        let cmdline = process.cmdline().ok().flatten().unwrap_or_default();
+
+        if patterns.is_match(&name, &cmdline) {
+            found_processes.push(FoundProcess { pid: process.pid(), name, cmdline, create_time: process.create_time().as_secs_f64() });
+        }
+    }
+
+    found_processes
+}
+
+// polls for processes matching the patterns (apart from those with the ignored PIDs) until some are found and returns them,
+// or returns an empty list if the timeout is reached, or the cancel flag is set first.
+pub fn wait_for_matching_processes(patterns: &ProcessPatterns, ignored_pids: &[u32], poll_interval: Duration,
+                                   timeout: Option<Duration>, cancel_flag: &AtomicBool) -> Vec<FoundProcess> {
+    let start_time = Instant::now();
+
+    loop {
+        let mut found_processes = find_matching_processes(patterns);
+        found_processes.retain(|process| !ignored_pids.contains(&process.pid));
+        if !found_processes.is_empty() {
+            return found_processes;
+        }
+
+        if cancel_flag.load(Ordering::SeqCst) || timeout.is_some_and(|timeout| start_time.elapsed() >= timeout) {
+            return Vec::new();
+        }
+
+        std::thread::sleep(poll_interval);
+    }
+}
+
+// returns the PIDs of psrec and all of its ancestor processes
+fn own_process_ancestry() -> Vec<u32> {
+    let mut pids = vec![std::process::id()];
+    while let Some(ppid) = Process::new(*pids.last().unwrap()).ok().and_then(|process| process.ppid().ok().flatten()) {
+        if ppid == 0 || pids.contains(&ppid) {
+            break;
+        }
+        pids.push(ppid);
+    }
+    pids
+}
+
+// selects which of the found processes to record, or returns None (after printing why) if there's no match,
+// or multiple matches when they're not allowed.
+pub fn select_processes(mut found_processes: Vec<FoundProcess>, selection: ProcessSelection) -> Option<Vec<FoundProcess>> {
+    if found_processes.is_empty() {
+        eprintln!("Error: no running processes matched.");
+        return None;
+    }
+
+    found_processes.sort_by(|a, b| a.create_time.total_cmp(&b.create_time).then(a.pid.cmp(&b.pid)));
+
+    match selection {
+        ProcessSelection::Newest =>     Some(vec![found_processes.pop().unwrap()]),
+        ProcessSelection::Oldest =>     Some(vec![found_processes.remove(0)]),
+        ProcessSelection::All =>        Some(found_processes),
+        ProcessSelection::Fail => {
+            if found_processes.len() > 1 {
+                eprintln!("Error: multiple processes matched:");
+                for process in &found_processes {
+                    eprintln!("  PID: {}\t{}\t{}", process.pid, process.name, process.cmdline);
+                }
+                eprintln!("Use --select newest/oldest/all to choose which to record.");
+                return None;
+            }
+            Some(found_processes)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn found_process(pid: u32, create_time: f64) -> FoundProcess {
+        FoundProcess { pid, name: "server".to_string(), cmdline: format!("server --port {}", pid), create_time }
+    }
+
+    fn selected_pids(selection: ProcessSelection) -> Option<Vec<u32>> {
+        let found_processes = vec![found_process(30, 20.0), found_process(10, 10.0), found_process(20, 30.0)];
+        select_processes(found_processes, selection).map(|processes| processes.iter().map(|process| process.pid).collect())
+    }
+
+    #[test]
+    fn test_select_processes() {
+        assert_eq!(selected_pids(ProcessSelection::Newest), Some(vec![20]));
+        assert_eq!(selected_pids(ProcessSelection::Oldest), Some(vec![10]));
+        assert_eq!(selected_pids(ProcessSelection::All), Some(vec![10, 30, 20]));
+        assert_eq!(selected_pids(ProcessSelection::Fail), None);
+
+        assert_eq!(select_processes(vec![found_process(10, 10.0)], ProcessSelection::Fail).map(|processes| processes.len()), Some(1));
+        assert!(select_processes(Vec::new(), ProcessSelection::All).is_none());
+    }
+
+    #[test]
+    fn test_process_patterns() {
+        let patterns = ProcessPatterns::new(Some("^nginx"), Some("worker")).unwrap();
+        assert!(patterns.is_match("nginx", "nginx: worker process"));
+        assert!(!patterns.is_match("nginx", "nginx: master process"));
+        assert!(!patterns.is_match("mynginx", "mynginx: worker process"));
+
+        assert!(ProcessPatterns::new(None, Some("(unclosed")).is_none());
+    }
+}
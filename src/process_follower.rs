@@ -0,0 +1,139 @@
+/*
+ psrec
+ Copyright 2022-2024 Peter Pearson.
+ Licensed under the Apache License, Version 2.0 (the "License");
+ You may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+ http://www.apache.org/licenses/LICENSE-2.0
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+ ---------
+*/
+
+use crate::oom_monitor::OomVerdict;
+use crate::process_finder::{find_matching_processes, ProcessPatterns};
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use psutil::process::Process;
+
+// how to find the replacement of a followed process (i.e. when a supervisor restarts it) once it has exited
+#[derive(Clone, Debug)]
+pub enum FollowTarget {
+    // the newest process matching the patterns
+    Patterns(ProcessPatterns),
+    // the process with the PID in the pid file
+    PidFile(PathBuf),
+}
+
+#[derive(Clone, Debug)]
+pub struct FollowParams {
+    pub target:         FollowTarget,
+
+    // how long to wait for a replacement once the process has exited before the recording stops.
+    pub timeout:        Duration,
+}
+
+// a switch of the recorded process to its replacement, as marked on the recording timeline
+#[derive(Clone, Debug)]
+pub struct PidSwitch {
+    // in seconds, since the start of the recording
+    pub elapsed_time:   f64,
+
+    pub old_pid:        u32,
+    pub new_pid:        u32,
+
+    // whether the old process was killed by the OOM killer
+    pub oom_verdict:    OomVerdict,
+}
+
+impl PidSwitch {
+    // returns the switch formatted as the value of a CSV metadata item
+    pub fn metadata_value(&self, time_precision: usize) -> String {
+        format!("{:.*},{},{},{}", time_precision, self.elapsed_time, self.old_pid, self.new_pid, self.oom_verdict.description())
+    }
+}
+
+// finds the replacements of a followed process when it exits
+pub struct ProcessFollower {
+    params:             FollowParams,
+
+    // other processes which matched the patterns when following started, which aren't replacements
+    ignored_pids:       Vec<u32>,
+
+    // when the followed process was found to have exited, if it has
+    exited_time:        Option<Instant>,
+}
+
+impl ProcessFollower {
+    pub fn new(params: FollowParams, pid: u32) -> ProcessFollower {
+        let ignored_pids = match &params.target {
+            FollowTarget::Patterns(patterns) => {
+                find_matching_processes(patterns).iter().map(|process| process.pid).filter(|other_pid| *other_pid != pid).collect()
+            },
+            FollowTarget::PidFile(_) => Vec::new(),
+        };
+
+        ProcessFollower { params, ignored_pids, exited_time: None }
+    }
+
+    // looks for the replacement of the exited process, which should be called periodically once the process has exited
+    // until one is found, or timed_out() returns true.
+    pub fn find_replacement(&mut self, exited_pid: u32) -> Option<Process> {
+        if self.exited_time.is_none() {
+            eprintln!("Followed process (PID: {}) has exited, waiting for its replacement...", exited_pid);
+            self.exited_time = Some(Instant::now());
+        }
+
+        let new_pid = match &self.params.target {
+            FollowTarget::Patterns(patterns) => {
+                find_matching_processes(patterns).iter()
+                    .filter(|process| process.pid != exited_pid && !self.ignored_pids.contains(&process.pid))
+                    .max_by(|a, b| a.create_time.total_cmp(&b.create_time))
+                    .map(|process| process.pid)
+            },
+            // the pid file might not have been updated yet, or might be in the process of being written
+            FollowTarget::PidFile(path) => read_pid_file(path).filter(|pid| *pid != exited_pid),
+        }?;
+
+        let process = Process::new(new_pid).ok().filter(|process| process.is_running())?;
+        self.exited_time = None;
+        Some(process)
+    }
+
+    pub fn timed_out(&self) -> bool {
+        self.exited_time.is_some_and(|exited_time| exited_time.elapsed() >= self.params.timeout)
+    }
+}
+
+// reads the PID from a pid file, as written by daemons and service supervisors
+pub fn read_pid_file(path: &Path) -> Option<u32> {
+    let contents = std::fs::read_to_string(path).ok()?;
+    contents.split_whitespace().next()?.parse::<u32>().ok().filter(|pid| *pid > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_pid_file() {
+        let path = std::env::temp_dir().join(format!("psrec_test_{}.pid", std::process::id()));
+
+        std::fs::write(&path, "1234\n").unwrap();
+        assert_eq!(read_pid_file(&path), Some(1234));
+
+        std::fs::write(&path, "").unwrap();
+        assert_eq!(read_pid_file(&path), None);
+
+        std::fs::write(&path, "not a pid").unwrap();
+        assert_eq!(read_pid_file(&path), None);
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read_pid_file(&path), None);
+    }
+}
@@ -14,22 +14,36 @@
 */
 
 use crate::process_sampler::{ProcessSampler, ProcessSamplerBasic};
+use crate::process_sampler_cgroup::ProcessSamplerCgroup;
 
 #[cfg(target_os = "linux")]
 use crate::process_sampler_advanced::ProcessSamplerAdvanced;
 
+use crate::adaptive_interval::{AdaptiveInterval, AdaptiveIntervalParams};
+use crate::cgroup::{Cgroup, CgroupParams};
+use crate::exit_info::ExitInfo;
+use crate::host_info::HostInfo;
+use crate::launch_info::LaunchInfo;
+use crate::oom_monitor::{OomMonitor, OomVerdict};
+use crate::output_capture::{OutputCapture, OutputCaptureParams, OutputStream};
+use crate::process_follower::{FollowParams, ProcessFollower, PidSwitch};
 use crate::process_samples::*;
+use crate::sample_scheduler::SampleScheduler;
+use crate::sample_trigger::{SampleTrigger, TriggerParams};
+use crate::stop_conditions::{StopCondition, StopConditionChecker, StopReason};
+use crate::terminate_policy::{TerminateParams, TerminatePolicy, TerminateSignal};
+use crate::target_info::{TargetInfo, DEFAULT_ENV_REDACTION_PATTERNS};
 use crate::utils::convert_time_period_string_to_ms;
 
-use std::process::Command;
+use std::process::Stdio;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
+use chrono::Local;
 use psutil::process::Process;
 
-// TODO: there is a bit of duplication in here between the two recording methods...
-
 #[derive(Clone, Debug)]
 pub struct ProcessRecordParams {
     // in ms
@@ -37,14 +51,24 @@ pub struct ProcessRecordParams {
     // human readable string representation (with units) of the above, for printing
     pub sample_interval_human:  String,
 
+    // if set, the sample interval adapts between the min (which the sample interval above is then set to) and
+    // max intervals, depending on how quickly the values are changing
+    pub adaptive_interval:      Option<AdaptiveIntervalParams>,
+    // human readable string representation (with units) of the max interval, for printing
+    pub max_sample_interval_human:  String,
+
     // in ms
     pub record_duration:        Option<u64>,
     // human readable string representation (with units) of the above, for printing
     pub record_duration_human:  String, 
 
-    // whether to 'normalise' CPU usage values to the number of threads the process can use.
+    // whether to 'normalise' CPU usage values to the number of threads the process can use when printing them.
+    // Note: the recording itself always stores absolute values, normalisation of those happens on export.
     pub normalise_cpu_usage:    bool,
 
+    // thread count to normalise against, instead of the detected system thread count.
+    pub normalise_thread_count: Option<u32>,
+
     // whether to print values to stderr as they're sampled live...
     pub print_values:           bool,
 
@@ -53,6 +77,32 @@ pub struct ProcessRecordParams {
 
     // whether to record the thread count of the process
     pub record_thread_count:    bool,
+
+    // whether to record the OOM score of the process
+    pub record_oom_score:       bool,
+
+    // whether to record the environment variables of the process in the recording metadata
+    pub record_environment:     bool,
+
+    // environment variable name patterns to redact the values of, if recording the environment
+    pub env_redaction_patterns: Vec<String>,
+
+    // additional conditions to stop recording on (on top of the process exiting, cancelling and the record duration)
+    pub stop_conditions:        Vec<StopCondition>,
+
+    // if set, when the process being recorded exits, its replacement is found and recorded instead
+    pub follow:                 Option<FollowParams>,
+
+    // if set, samples are only kept around the times the trigger conditions are met
+    pub trigger:                Option<TriggerParams>,
+
+    // if set, the max number of samples to keep per series, with older samples being progressively downsampled
+    // to keep within it
+    pub max_samples:            Option<usize>,
+
+    // details of the host machine to write to the recording metadata. This is gathered once up front, rather
+    // than for each recording (i.e. for each run when benchmarking).
+    pub host_info:              HostInfo,
 }
 
 impl ProcessRecordParams {
@@ -60,12 +110,23 @@ impl ProcessRecordParams {
 
         let mut params = ProcessRecordParams { sample_interval: 1000,
                                                sample_interval_human: "1 sec".to_string(),
+                                               adaptive_interval: None,
+                                               max_sample_interval_human: String::new(),
                                                record_duration: None,
                                                record_duration_human: String::new(),
                                                normalise_cpu_usage: false,
+                                               normalise_thread_count: None,
                                                print_values: false,
                                                record_child_processes: false,
-                                               record_thread_count: false };
+                                               record_thread_count: false,
+                                               record_oom_score: false,
+                                               record_environment: false,
+                                               env_redaction_patterns: DEFAULT_ENV_REDACTION_PATTERNS.iter().map(|pattern| pattern.to_string()).collect(),
+                                               stop_conditions: Vec::new(),
+                                               follow: None,
+                                               trigger: None,
+                                               max_samples: None,
+                                               host_info: HostInfo::default() };
 
         if let Some(sample_interval_string) = sample_interval {
             if let Some(interval_ms) = convert_time_period_string_to_ms(&sample_interval_string) {
@@ -96,6 +157,16 @@ impl ProcessRecordParams {
         self.normalise_cpu_usage = normalise_cpu_usage;
     }
 
+    pub fn set_normalise_thread_count(&mut self, normalise_thread_count: u32) {
+        self.normalise_thread_count = Some(normalise_thread_count);
+    }
+
+    // returns the export params to print values live with, so they're normalised in the same way as when exported
+    pub fn print_export_params(&self) -> ExportParams {
+        ExportParams { normalise_cpu_usage: self.normalise_cpu_usage, normalise_thread_count: self.normalise_thread_count,
+                       ..Default::default() }
+    }
+
     pub fn set_print_values(&mut self, print_values: bool) {
         self.print_values = print_values;
     }
@@ -107,363 +178,979 @@ impl ProcessRecordParams {
     pub fn set_record_thread_count(&mut self, record_thread_count: bool) {
         self.record_thread_count = record_thread_count;
     }
+
+    pub fn set_record_oom_score(&mut self, record_oom_score: bool) {
+        self.record_oom_score = record_oom_score;
+    }
+
+    pub fn set_record_environment(&mut self, record_environment: bool) {
+        self.record_environment = record_environment;
+    }
+
+    pub fn add_env_redaction_pattern(&mut self, pattern: &str) {
+        self.env_redaction_patterns.push(pattern.to_string());
+    }
+
+    pub fn add_stop_condition(&mut self, stop_condition: StopCondition) {
+        self.stop_conditions.push(stop_condition);
+    }
+
+    pub fn set_follow(&mut self, follow: FollowParams) {
+        self.follow = Some(follow);
+    }
+
+    pub fn set_trigger(&mut self, trigger: TriggerParams) {
+        self.trigger = Some(trigger);
+    }
+
+    pub fn set_max_samples(&mut self, max_samples: usize) {
+        self.max_samples = Some(max_samples);
+    }
+
+    pub fn set_host_info(&mut self, host_info: HostInfo) {
+        self.host_info = host_info;
+    }
+
+    // sets the sample interval to adapt between the min and max intervals (in ms, with their human readable strings)
+    pub fn set_adaptive_interval(&mut self, min_interval: (u64, String), max_interval: (u64, String)) {
+        self.adaptive_interval = Some(AdaptiveIntervalParams { min_interval: Duration::from_millis(min_interval.0),
+                                                               max_interval: Duration::from_millis(max_interval.0) });
+        self.sample_interval = min_interval.0;
+        self.sample_interval_human = min_interval.1;
+        self.max_sample_interval_human = max_interval.1;
+    }
+}
+
+// the ways recording a process can fail, which psrec exits with different exit codes for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RecordError {
+    // the process couldn't be attached to
+    AttachFailed,
+    // the process sampler couldn't be initialised for the process
+    SamplerInitFailed,
+    // the command to start couldn't be found
+    CommandNotFound,
+    // the command to start was found, but couldn't be run (i.e. due to permissions)
+    CommandNotExecutable,
+    // anything else, i.e. the output log file couldn't be created
+    Other,
 }
 
 pub trait ProcessRecorder {
-    fn start(&mut self, cancel_flag: Arc<AtomicBool>) -> bool;
+    fn start(&mut self, cancel_flag: Arc<AtomicBool>) -> Result<(), RecordError>;
 
     fn get_recording(&self) -> ProcessRecording;
 }
 
+// checks whether the process being recorded is still running, which needs to be done differently for
+// processes we've attached to, and processes we've started ourselves.
+pub trait ProcessWatcher {
+    fn is_running(&mut self) -> bool;
+
+    // how the process exited, once it has, if that's known
+    fn exit_info(&self) -> Option<ExitInfo> {
+        None
+    }
+
+    // replaces the process at the index with its replacement, when following restarts
+    fn replace_process(&mut self, _index: usize, _process: Process) {
+    }
+}
+
+// watches one or more attached processes, which are considered to be running until all of them have exited.
+pub struct AttachedProcessWatcher {
+    processes:  Vec<Process>,
+
+    // whether each of the processes was still running when last checked
+    running:    Vec<bool>,
+}
+
+impl AttachedProcessWatcher {
+    pub fn new(processes: Vec<Process>) -> AttachedProcessWatcher {
+        let running = vec![true; processes.len()];
+        AttachedProcessWatcher { processes, running }
+    }
+}
+
+impl ProcessWatcher for AttachedProcessWatcher {
+    // Note: this apparently can't be relied on for processes we fork/spawn ourselves...
+    fn is_running(&mut self) -> bool {
+        for (process, running) in self.processes.iter().zip(self.running.iter_mut()) {
+            // Note: calling psutil::process::Process::is_running() on a process we spawned ourself
+            // is apparently not useful, as it still returns true even when the process has actually exited.
+            if *running && !process.is_running() {
+                *running = false;
+                if self.processes.len() > 1 {
+                    eprintln!("Attached process (PID: {}) has exited.", process.pid());
+                }
+            }
+        }
+
+        self.running.iter().any(|running| *running)
+    }
+
+    fn replace_process(&mut self, index: usize, process: Process) {
+        self.processes[index] = process;
+        self.running[index] = true;
+    }
+}
+
+pub struct ChildProcessWatcher {
+    // actual spawned/forked process ownership
+    child:      std::process::Child,
+
+    exit_info:  Option<ExitInfo>,
+
+    // whether the process was started in its own process group, in which case signals are sent to the whole group
+    own_process_group:  bool,
+
+    // when the process was started, and a thread which returns when it exited, so the wall time is exact rather than
+    // depending on when it's next polled
+    start_time:         Instant,
+    exit_waiter:        Option<JoinHandle<Option<Instant>>>,
+}
+
+impl ChildProcessWatcher {
+    fn new(child: std::process::Child, start_time: Instant, own_process_group: bool) -> ChildProcessWatcher {
+        // wait for the process to exit without reaping it, so that is_running() can still get its exit status and
+        // resource usage.
+        #[cfg(unix)]
+        let exit_waiter = {
+            let pid = child.id() as libc::id_t;
+            std::thread::Builder::new().name("exit_waiter".to_string()).spawn(move || {
+                let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+                let res = unsafe { libc::waitid(libc::P_PID, pid, &mut info, libc::WEXITED | libc::WNOWAIT) };
+                (res == 0).then(Instant::now)
+            }).ok()
+        };
+        #[cfg(not(unix))]
+        let exit_waiter = None;
+
+        ChildProcessWatcher { child, exit_info: None, own_process_group, start_time, exit_waiter }
+    }
+
+    // returns the wall time of the process, once it has exited
+    fn exit_wall_time(&mut self) -> f64 {
+        let exit_time = self.exit_waiter.take().and_then(|exit_waiter| exit_waiter.join().ok().flatten());
+        exit_time.unwrap_or_else(Instant::now).duration_since(self.start_time).as_secs_f64()
+    }
+
+    // sends the signal to the process (or its process group), returning whether it could be sent.
+    fn send_signal(&mut self, signal: TerminateSignal) -> bool {
+        #[cfg(unix)]
+        {
+            let pid = self.child.id() as libc::pid_t;
+            let target_pid = if self.own_process_group { -pid } else { pid };
+            unsafe { libc::kill(target_pid, signal.number()) == 0 }
+        }
+
+        #[cfg(not(unix))]
+        {
+            // the best we can do is kill the process
+            if signal == TerminateSignal::Interrupt {
+                eprintln!("Warning: Forwarding signals to the process is only supported on Unix platforms.");
+                return false;
+            }
+            self.child.kill().is_ok()
+        }
+    }
+
+    // kills the process (or its process group), and waits for it to exit, so that it isn't left running (or as a zombie)
+    fn kill_and_reap(&mut self) {
+        self.send_signal(TerminateSignal::Kill);
+        let _ = self.child.wait();
+    }
+}
+
+impl ProcessWatcher for ChildProcessWatcher {
+    // this is needed because we can't rely on psutil::process::Process::is_running() working
+    // on a forked/spawned process we started ourselves apparently.
+    fn is_running(&mut self) -> bool {
+        if self.exit_info.is_some() {
+            return false;
+        }
+
+        // check if the process has exited with an exit code.
+        // This does not block, so we can call it as a poll-like event loop
+        #[cfg(unix)]
+        {
+            // use wait4() directly rather than try_wait(), so that we get the final resource usage of the process as well.
+            // Note: the process is reaped by this, so try_wait() can't be used on the Child after this point.
+            let pid = self.child.id() as libc::pid_t;
+            let mut status: libc::c_int = 0;
+            let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+            let res = loop {
+                let res = unsafe { libc::wait4(pid, &mut status, libc::WNOHANG, &mut rusage) };
+                if res != -1 || std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted {
+                    break res;
+                }
+            };
+            if res == 0 {
+                // still running
+                return true;
+            }
+            if res == pid {
+                let mut exit_info = ExitInfo::from_wait_status(status, Some(&rusage));
+                exit_info.wall_time = Some(self.exit_wall_time());
+                self.exit_info = Some(exit_info);
+            }
+            else {
+                // the process can't be waited for any more, so treat it as exited, but without any exit info
+                eprintln!("Error waiting for the process to exit: {}", std::io::Error::last_os_error());
+            }
+            false
+        }
+
+        #[cfg(not(unix))]
+        {
+            match self.child.try_wait() {
+                Ok(Some(status)) => {
+                    let mut exit_info = ExitInfo::from_exit_status(&status);
+                    exit_info.wall_time = Some(self.exit_wall_time());
+                    self.exit_info = Some(exit_info);
+                    false
+                },
+                Ok(None) =>             true, // still running
+                Err(err) => {
+                    // the process can't be waited for any more, so treat it as exited, but without any exit info
+                    eprintln!("Error waiting for the process to exit: {}", err);
+                    false
+                }
+            }
+        }
+    }
+
+    fn exit_info(&self) -> Option<ExitInfo> {
+        self.exit_info.clone()
+    }
+}
+
+// a process being recorded, along with the sampler for it
+struct RecordedProcess {
+    process:            Process,
+
+    sampler:            Option<Box<dyn ProcessSampler>>,
+
+    // index of the series in the recording the samples of the process are added to
+    series_index:       usize,
+}
+
 pub struct ProcessRecorderCore {
     recorder_params:    ProcessRecordParams,
-    process:            Option<Process>,
+    processes:          Vec<RecordedProcess>,
 
     print_values:       bool,
 
     pub recording:      ProcessRecording,
 
-    pub sampler:        Option<Box<dyn ProcessSampler>>,
+    oom_monitor:        Option<OomMonitor>,
+
+    // whether the identifying details of the main process still need to be gathered, which for a started process
+    // is only done once sampling it has started (and retried until its details can be read), as it might not have
+    // been exec'd yet when it's first added.
+    target_info_pending: bool,
+
+    // finds the replacement of the main process when it exits, if following restarts
+    follower:           Option<ProcessFollower>,
 
-    pub start_time:     Option<SystemTime>,
+    // decides which samples to keep, in trigger mode
+    trigger:            Option<SampleTrigger>,
+
+    // works out the interval until the next sample, when sampling adaptively
+    adaptive_interval:  Option<AdaptiveInterval>,
+
+    // monotonic start time of the recording, which the sample times are relative to
+    pub start_time:     Option<Instant>,
+
+    scheduler:          Option<SampleScheduler>,
 }
 
 impl ProcessRecorderCore {
     pub fn from_params(params: &ProcessRecordParams) -> ProcessRecorderCore {
         ProcessRecorderCore { recorder_params: params.clone(),
-                              process: None,
+                              processes: Vec::new(),
                               print_values: params.print_values,
                               recording: ProcessRecording::new(params, 0),
-                              sampler: None,
-                              start_time: None }
+                              oom_monitor: None,
+                              target_info_pending: false,
+                              follower: None,
+                              trigger: params.trigger.clone().map(|trigger_params| SampleTrigger::new(trigger_params, Duration::from_millis(params.sample_interval))),
+                              adaptive_interval: params.adaptive_interval.as_ref().map(AdaptiveInterval::new),
+                              start_time: None,
+                              scheduler: None }
+    }
+
+    // marks the start of the recording, which sample times are relative to.
+    fn start_recording_timer(&mut self) {
+        let start_time = Instant::now();
+        self.start_time = Some(start_time);
+        self.recording.start_timestamp = Local::now();
+
+        let sample_interval = Duration::from_millis(self.recorder_params.sample_interval);
+        self.scheduler = Some(SampleScheduler::new(start_time, sample_interval));
+    }
+
+    // waits until it's time to take the next sample
+    fn wait_for_next_sample(&mut self) {
+        self.scheduler.as_mut().unwrap().wait_for_next_tick();
+    }
+
+    // when sampling adaptively, works out when to take the next sample, based on the latest sample
+    fn update_sample_interval(&mut self, latest_sample: Option<&Sample>) {
+        if let Some(adaptive_interval) = self.adaptive_interval.as_mut() {
+            let tick_step = adaptive_interval.next_steps(self.start_time.unwrap().elapsed(), latest_sample);
+            self.scheduler.as_mut().unwrap().set_tick_step(tick_step);
+        }
+    }
+
+    // returns a copy of the recording, with the final stats of the recording process filled in.
+    // TODO: get rid of the need to do this with a copy...
+    fn recording(&self) -> ProcessRecording {
+        let mut recording = self.recording.clone();
+        if let Some(scheduler) = &self.scheduler {
+            recording.scheduler_stats = Some(scheduler.stats().clone());
+        }
+        if let Some(trigger) = &self.trigger {
+            recording.trigger_events = trigger.events().to_vec();
+        }
+        recording
+    }
+
+    // returns a description of how often and for how long samples are going to be recorded
+    fn recording_description(&self) -> String {
+        let mut recording_msg = if self.recorder_params.adaptive_interval.is_some() {
+            format!("Recording samples adaptively every {} to {} ", self.recorder_params.sample_interval_human,
+                    self.recorder_params.max_sample_interval_human)
+        }
+        else {
+            format!("Recording samples every {} ", self.recorder_params.sample_interval_human)
+        };
+        if self.recorder_params.record_duration.is_none() {
+            recording_msg.push_str("until process ends...");
+        }
+        else {
+            recording_msg.push_str(&format!("for a duration of {}...", self.recorder_params.record_duration_human));
+        }
+        if let Some(trigger_params) = &self.recorder_params.trigger {
+            let conditions: Vec<String> = trigger_params.conditions.iter().map(|condition| condition.description()).collect();
+            recording_msg.push_str(&format!("\nTrigger mode: only keeping samples from {:.1} secs before to {:.1} secs after: {}.",
+                                   trigger_params.pre_trigger.as_secs_f64(), trigger_params.post_trigger.as_secs_f64(),
+                                   conditions.join(" or ")));
+        }
+        recording_msg
+    }
+
+    // returns the full list of conditions which should stop the recording
+    fn stop_conditions(&self, cancel_flag: Arc<AtomicBool>) -> Vec<StopCondition> {
+        let mut stop_conditions = vec![StopCondition::TargetExited, StopCondition::Cancelled(cancel_flag)];
+        if let Some(record_duration_limit) = self.recorder_params.record_duration {
+            stop_conditions.push(StopCondition::Duration(Duration::from_millis(record_duration_limit)));
+        }
+        stop_conditions.extend(self.recorder_params.stop_conditions.iter().cloned());
+        stop_conditions
     }
 
-    fn init_sampler(&mut self) -> bool {
-        if self.process.is_none() {
+    // adds a process to record as a new named series. The first process added is the main process being recorded,
+    // so the identifying details of it are recorded in the recording, and it's monitored for OOM kills.
+    fn add_process(&mut self, process: Process, name: &str) {
+        if self.processes.is_empty() {
+            self.recording.initial_process_id = process.pid();
+            self.recording.current_process_id = process.pid();
+            self.target_info_pending = true;
+            if self.recording.launch_info.is_none() {
+                self.update_target_info(process.pid());
+            }
+
+            self.oom_monitor = Some(OomMonitor::new(process.pid(), self.recorder_params.record_oom_score));
+
+            if let Some(follow_params) = &self.recorder_params.follow {
+                self.follower = Some(ProcessFollower::new(follow_params.clone(), process.pid()));
+            }
+        }
+
+        let series_index = self.recording.add_series(name, process.pid());
+        self.processes.push(RecordedProcess { process, sampler: None, series_index });
+    }
+
+    // gathers the identifying details of the main process, which for a started process is retried on later samples
+    // if the command line (or environment) couldn't be read yet.
+    fn update_target_info(&mut self, pid: u32) {
+        let target_info = TargetInfo::gather(pid, self.recorder_params.record_environment,
+                                             &self.recorder_params.env_redaction_patterns);
+        self.target_info_pending = self.recording.launch_info.is_some() &&
+                                   (target_info.cmdline.is_empty() || (self.recorder_params.record_environment && target_info.environment.is_none()));
+        self.recording.set_target_info(target_info);
+    }
+
+    // fills in any details of the main process which couldn't be gathered with what it was started with
+    fn finish_target_info(&mut self) {
+        let Some(launch_info) = &self.recording.launch_info else {
+            return;
+        };
+        let target_info = self.recording.target_info.get_or_insert_with(|| TargetInfo { pid: self.recording.initial_process_id,
+                                                                                          ..Default::default() });
+        if target_info.cmdline.is_empty() {
+            target_info.cmdline = launch_info.argv.clone();
+        }
+    }
+
+    fn init_samplers(&mut self) -> bool {
+        if self.processes.is_empty() {
             return false;
         }
 
+        for index in 0..self.processes.len() {
+            let sampler = self.create_sampler(self.processes[index].process.pid());
+            if sampler.is_none() {
+                return false;
+            }
+            self.processes[index].sampler = sampler;
+        }
+
+        true
+    }
+
+    fn create_sampler(&self, pid: u32) -> Option<Box<dyn ProcessSampler>> {
         let need_advanced = self.recorder_params.record_child_processes ||
                             self.recorder_params.record_thread_count;
  
         if need_advanced {
             #[cfg(target_os = "linux")]
             {
-                let new_advanced_sampler = ProcessSamplerAdvanced::new(self.recorder_params.clone(), self.process.as_ref().unwrap().pid());
+                let new_advanced_sampler = ProcessSamplerAdvanced::new(self.recorder_params.clone(), pid);
                 if new_advanced_sampler.is_none() {
                     eprintln!("Error creating advanced sampler.");
-                    return false;
+                    return None;
                 }
 
-                self.sampler = Some(Box::new(new_advanced_sampler.unwrap()));
-                return true;
+                Some(Box::new(new_advanced_sampler.unwrap()))
             }
             
             #[cfg(not(target_os = "linux"))]
             {
                 eprintln!("Error: Advanced features are only available in Linux builds with access to the /proc/<pid>/stat file system.");
-                return false;
+                None
             }
         }
         else {
             // handle the basic/backup case...
-            // TODO: handle unwraps() correctly...
-            let new_basic_sampler = ProcessSamplerBasic::new(self.process.as_ref().unwrap().pid());
-            self.sampler = Some(Box::new(new_basic_sampler.unwrap()));
+            let new_basic_sampler = ProcessSamplerBasic::new(pid)?;
+            Some(Box::new(new_basic_sampler))
         }
+    }
 
-        true
+    // the recording loop used for all recording modes: records samples at each sample interval until one of the stop
+    // conditions is met, and then returns the reason the recording stopped (which is also stored in the recording).
+    // Note: the timer should have been started and the sampler initialised before calling this.
+    fn record_until_stopped(&mut self, watcher: &mut dyn ProcessWatcher, stop_conditions: Vec<StopCondition>) -> StopReason {
+        let mut stop_condition_checker = StopConditionChecker::new(stop_conditions);
+
+        let first_sample = self.record_samples();
+        self.update_sample_interval(first_sample.as_ref());
+
+        let stop_reason = loop {
+            self.wait_for_next_sample();
+
+            let mut target_running = watcher.is_running();
+
+            // if following restarts, keep going while waiting for the replacement of the main process
+            let mut waiting_for_replacement = false;
+            if !target_running && self.follower.is_some() {
+                let exited_pid = self.processes[0].process.pid();
+                if let Some(process) = self.follower.as_mut().unwrap().find_replacement(exited_pid) {
+                    target_running = self.switch_process(process, watcher);
+                }
+                else if self.follower.as_ref().unwrap().timed_out() {
+                    eprintln!("Timed out waiting for a replacement of the followed process.");
+                }
+                else {
+                    waiting_for_replacement = true;
+                }
+            }
+
+            let latest_sample = if target_running { self.record_samples() } else { None };
+            self.update_sample_interval(latest_sample.as_ref());
+
+            let elapsed = self.start_time.unwrap().elapsed();
+            if let Some(stop_reason) = stop_condition_checker.check(target_running || waiting_for_replacement,
+                                                                   latest_sample.as_ref(), elapsed) {
+                break stop_reason;
+            }
+
+            if !target_running && !waiting_for_replacement {
+                // there's nothing more we can record...
+                break StopReason::TargetExited;
+            }
+        };
+
+        if stop_reason == StopReason::TargetExited {
+            self.finish_target_exited(watcher);
+        }
+
+        self.finish_target_info();
+
+        self.recording.stop_reason = Some(stop_reason);
+
+        stop_reason
     }
 
-    // Note: this apparently can't be relied on for processes we fork/spawn ourselves...
-    fn process_is_running(&mut self) -> bool {
-        if self.process.is_none() {
-            return false;
+    // records how the process exited (if known), and whether it was killed by the OOM killer or from hitting a resource
+    // limit, once it has exited.
+    fn finish_target_exited(&mut self, watcher: &dyn ProcessWatcher) {
+        self.recording.exit_info = watcher.exit_info();
+
+        let exit_signal = self.recording.exit_info.as_ref().and_then(|exit_info| exit_info.signal);
+        self.finish_oom_monitor(exit_signal, self.recording.exit_info.is_some());
+
+        if let (Some(launch_info), Some(exit_info)) = (&self.recording.launch_info, &self.recording.exit_info) {
+            let oom_verdict = self.recording.oom_info.as_ref().map_or(OomVerdict::NotKilled, |oom_info| oom_info.verdict);
+            self.recording.limit_hit = launch_info.limits.limit_hit(exit_info, oom_verdict);
         }
+    }
+
+    // switches recording the (exited) main process to its replacement, marking the switch on the timeline.
+    fn switch_process(&mut self, process: Process, watcher: &mut dyn ProcessWatcher) -> bool {
+        let old_pid = self.processes[0].process.pid();
+        let new_pid = process.pid();
 
-        // Note: calling psutil::process::Process::is_running() on a process we spawned ourself
-        // is apparently not useful, as it still returns true even when the process has actually exited.
-        if self.process.as_ref().unwrap().is_running() {
-            return true;
+        let sampler = self.create_sampler(new_pid);
+        if sampler.is_none() {
+            eprintln!("Error initialising process sampler for the replacement process (PID: {}).", new_pid);
+            return false;
         }
 
-        false
+        // work out whether the old process was killed by the OOM killer before monitoring the new one
+        let oom_verdict = self.oom_monitor.as_mut().map_or(OomVerdict::NotKilled, |oom_monitor| oom_monitor.finish(None, false).verdict);
+        self.oom_monitor = Some(OomMonitor::new(new_pid, self.recorder_params.record_oom_score));
+
+        let elapsed_time = self.start_time.unwrap().elapsed().as_secs_f64();
+        self.recording.pid_switches.push(PidSwitch { elapsed_time, old_pid, new_pid, oom_verdict });
+        self.recording.current_process_id = new_pid;
+
+        eprintln!("Following replacement process (PID: {}) of process (PID: {}) ({}).", new_pid, old_pid, oom_verdict.description());
+
+        self.processes[0].process = process.clone();
+        self.processes[0].sampler = sampler;
+        watcher.replace_process(0, process);
+
+        true
     }
 
-    fn record_sample(&mut self) {
-        if self.process.is_none() {
-            return;
+    // works out whether the process was killed by the OOM killer, once the process has exited.
+    fn finish_oom_monitor(&mut self, exit_signal: Option<i32>, signal_known: bool) {
+        if let Some(oom_monitor) = self.oom_monitor.as_mut() {
+            self.recording.oom_info = Some(oom_monitor.finish(exit_signal, signal_known));
         }
+    }
 
-        let elapsed_time = self.start_time.unwrap().elapsed().unwrap().as_secs_f32();
+    // records a sample of each process, all with the same elapsed time, and returns the total of the samples taken
+    // (which is just the sample itself when recording a single process), or None if none could be taken.
+    fn record_samples(&mut self) -> Option<Sample> {
+        let elapsed_time = self.start_time.unwrap().elapsed().as_secs_f64();
+
+        let multiple_processes = self.processes.len() > 1;
+        let print_thread_count = self.recorder_params.print_export_params().normalisation_thread_count(&self.recording);
+        let mut total_sample: Option<Sample> = None;
+        // the samples taken, with the index of the series each is for
+        let mut samples = Vec::with_capacity(self.processes.len());
+
+        for (index, recorded_process) in self.processes.iter_mut().enumerate() {
+            // TODO: handle this more correctly...
+            let sample = recorded_process.sampler.as_mut().unwrap().get_sample();
+            if sample.is_none() {
+                // assume the first sample might be dud (or the process has exited), and ignore it...
+                continue;
+            }
+            let mut sample = sample.unwrap();
 
-        // TODO: handle this more correctly...
-        let sample = self.sampler.as_mut().unwrap().get_sample();
-        if sample.is_none() {
-            // assume the first sample might be dud, and ignore it...
-            return;
+            // set the elapsed_time correctly...
+            sample.elapsed_time = elapsed_time;
+
+            // only the main process is monitored for OOM kills
+            if index == 0 {
+                if let Some(oom_monitor) = self.oom_monitor.as_mut() {
+                    if let Some(oom_score) = oom_monitor.update() {
+                        sample.oom_score = oom_score;
+                    }
+                }
+            }
+
+            if self.print_values {
+                // Note: the sample itself is always kept with the absolute value, we only normalise it for display here if requested.
+                let cpu_usage = ProcessRecording::cpu_usage_value(&sample, print_thread_count);
+                let series_name = &self.recording.series[recorded_process.series_index].name;
+                let series_prefix = if multiple_processes { format!("[{}]\t", series_name) } else { String::new() };
+                eprintln!("{}Time: {:.2}\tCPU: {:.1}%\t\tMem: {} KB", series_prefix, sample.elapsed_time, cpu_usage, sample.curr_rss / 1024);
+            }
+
+            if let Some(total) = total_sample.as_mut() {
+                total.accumulate(&sample);
+            }
+            else {
+                total_sample = Some(sample.clone());
+            }
+
+            samples.push((recorded_process.series_index, sample));
         }
-        let mut sample = sample.unwrap();
 
-        // set the elapsed_time correctly...
-        sample.elapsed_time = elapsed_time;
+        // only retry while the main process is still the one the details are for (i.e. not after following a restart)
+        if self.target_info_pending {
+            let pid = self.processes[0].process.pid();
+            if pid == self.recording.initial_process_id {
+                self.update_target_info(pid);
+            }
+            else {
+                self.target_info_pending = false;
+            }
+        }
 
-        if self.recorder_params.normalise_cpu_usage {
-            // so because of that, normalise the value to the number of threads if requested (which is the default).
-            sample.cpu_usage /= self.recording.num_system_threads as f32;
+        // in trigger mode, only the samples around the triggers are kept
+        if let Some(trigger) = self.trigger.as_mut() {
+            samples = trigger.process_samples(elapsed_time, total_sample.as_ref(), samples);
         }
 
-        if self.print_values {
-            eprintln!("Time: {:.2}\tCPU: {:.1}%\t\tMem: {} KB", sample.elapsed_time, sample.cpu_usage, sample.curr_rss / 1024);
+        for (series_index, sample) in samples {
+            self.recording.series[series_index].samples.push(sample);
         }
 
-        self.recording.samples.push(sample);
+        // keep the memory use bounded by keeping the most recent half of the samples at full resolution,
+        // and halving the resolution of the older half each time the limit is reached. The same time range is
+        // downsampled in all the series (based on the longest one), so that they still line up.
+        if let Some(max_samples) = self.recorder_params.max_samples {
+            let longest_series = self.recording.series.iter().max_by_key(|single_series| single_series.samples.len());
+            if let Some(longest_series) = longest_series.filter(|single_series| single_series.samples.len() >= max_samples) {
+                let end_time = longest_series.samples[max_samples / 2].elapsed_time;
+                self.recording.downsample_before(end_time);
+                self.recording.downsampled = true;
+            }
+        }
+
+        total_sample
     }
 }
 
 pub struct ProcessRecorderAttach {
-    record_params:  ProcessRecordParams,
-
     core:           ProcessRecorderCore,
+
+    watcher:        AttachedProcessWatcher,
 }
 
 impl ProcessRecorderAttach {
-    pub fn new(pid: u32, record_params: &ProcessRecordParams) -> Option<ProcessRecorderAttach> {
-        let process = Process::new(pid);
-        if let Err(err) = process {
-            eprintln!("Error attaching to PID: {}, {}", pid, err);
-            return None;
+    // attaches to each of the processes, which are each recorded as a series named after the process, unless
+    // a name is provided. The first process is the main one being recorded.
+    pub fn new(targets: &[(u32, Option<String>)], record_params: &ProcessRecordParams) -> Option<ProcessRecorderAttach> {
+        let mut processes = Vec::with_capacity(targets.len());
+        let mut names = Vec::with_capacity(targets.len());
+        for (pid, name) in targets {
+            let process = Process::new(*pid);
+            if let Err(err) = process {
+                eprintln!("Error attaching to PID: {}, {}", pid, err);
+                return None;
+            }
+            let process = process.unwrap();
+
+            names.push(name.clone().unwrap_or_else(|| process.name().unwrap_or_else(|_| pid.to_string())));
+            processes.push(process);
         }
 
         let mut core = ProcessRecorderCore::from_params(record_params);
-        core.process = Some(process.unwrap());
+        for (process, name) in processes.iter().zip(&names) {
+            // make sure the series names are unique, as multiple processes (i.e. workers) often have the same name
+            if names.iter().filter(|other_name| *other_name == name).count() > 1 {
+                core.add_process(process.clone(), &format!("{}-{}", name, process.pid()));
+            }
+            else {
+                core.add_process(process.clone(), name);
+            }
+        }
 
-        Some(ProcessRecorderAttach { record_params: record_params.clone(), core })
+        Some(ProcessRecorderAttach { core, watcher: AttachedProcessWatcher::new(processes) })
     }
 }
 
 impl ProcessRecorder for ProcessRecorderAttach {
-    fn start(&mut self, cancel_flag: Arc<AtomicBool>) -> bool {
+    fn start(&mut self, cancel_flag: Arc<AtomicBool>) -> Result<(), RecordError> {
 
-        if !self.core.init_sampler() {
+        if !self.core.init_samplers() {
             eprintln!("Error initialising process sampler.");
-            return false;
+            return Err(RecordError::SamplerInitFailed);
         }
 
-        let mut recording_msg = format!("Recording samples every {} ", self.record_params.sample_interval_human);
-        if self.core.recorder_params.record_duration.is_none() {
-            recording_msg.push_str("until process ends...");
+        if self.core.recording.series.len() == 1 {
+            let command_line = self.core.recording.target_info.as_ref().map(|info| info.cmdline_string()).unwrap_or_default();
+            eprintln!("Successfully attached to process (PID: {}): {}\n{}",
+                        self.core.recording.initial_process_id,
+                        command_line,
+                        self.core.recording_description());
         }
         else {
-            recording_msg.push_str(&format!("for a duration of {}...", self.core.recorder_params.record_duration_human));
+            let series: Vec<String> = self.core.recording.series.iter().map(|series| format!("{} (PID: {})", series.name, series.pid)).collect();
+            eprintln!("Successfully attached to processes: {}\n{}", series.join(", "), self.core.recording_description());
         }
-
-        eprintln!("Successfully attached to process (PID: {}).\n{}",
-                    self.core.process.as_ref().unwrap().pid(),
-                    recording_msg);
         
-        self.core.start_time = Some(SystemTime::now());
-
-        self.core.record_sample();
-
-        let sleep_duration = std::time::Duration::from_millis(self.record_params.sample_interval);
-
-        std::thread::sleep(sleep_duration);
-
-        // this is a little bit silly, but we only want to pay the overhead if strictly necessary, so split the code
-        // paths so we're ultra-efficient while recording...
-        if let Some(record_duration_limit) = self.core.recorder_params.record_duration {
-            // we have a duration limit, so...
-            let duration_limit_secs = record_duration_limit as f32;
+        self.core.start_recording_timer();
 
-            while self.core.process_is_running() {
-                self.core.record_sample();
-
-                if cancel_flag.load(Ordering::SeqCst) {
-                    eprintln!("Manually cancelled, recording has stopped (process might continue running).");
-                    return true;
-                }
-    
-                // TODO: this suffers from a tiny bit of drift...
-                std::thread::sleep(sleep_duration);
-
-                // TODO: error handling...
-                let start_time = self.core.start_time.as_ref().unwrap();
-                let elapsed_time = start_time.elapsed();
-                // TODO: error handling...
-                if elapsed_time.unwrap().as_secs_f32() >= duration_limit_secs {
-                    eprintln!("Recording duration limit reached, recording has stopped (process might continue running).");
-                    return true;
-                }
-            }
-        }
-        else {
-            // we have no duration limit, so just do the basic stuff...
-
-            while self.core.process_is_running() {
-                self.core.record_sample();
-
-                if cancel_flag.load(Ordering::SeqCst) {
-                    eprintln!("Manually cancelled, recording has stopped (process might continue running).");
-                    return true;
-                }
-    
-                // TODO: this suffers from a tiny bit of drift...
-                std::thread::sleep(sleep_duration);
-            }
+        let stop_conditions = self.core.stop_conditions(cancel_flag);
+        let stop_reason = self.core.record_until_stopped(&mut self.watcher, stop_conditions);
+        if stop_reason != StopReason::TargetExited {
+            eprintln!("{}, recording has stopped (process might continue running).", stop_reason.description());
         }
         
-        true
+        Ok(())
     }
 
-    // TODO: get rid of the need to do this with a copy...
     fn get_recording(&self) -> ProcessRecording {
-        self.core.recording.clone()
+        self.core.recording()
     }
 }
 
 pub struct ProcessRecorderRun {
-    record_params:  ProcessRecordParams,
+    launch_info:    LaunchInfo,
+
+    // if set, the stdout/stderr of the process is captured
+    output_capture: Option<OutputCaptureParams>,
 
-    command:        String,
-    args:           Option<Vec<String>>,
+    // if set, the process is started in its own cgroup, and all the processes in it are recorded as a whole
+    cgroup:         Option<CgroupParams>,
+
+    // what to do with the process if the recording is stopped before it exits
+    terminate:      TerminateParams,
 
     core:           ProcessRecorderCore,
 
-    // actual spawned/forked process ownership
-    child_process:  Option<std::process::Child>,
+    watcher:        Option<ChildProcessWatcher>,
 }
 
 impl ProcessRecorderRun {
-    pub fn new(command: &str, args: Option<Vec<String>>, record_params: &ProcessRecordParams) -> Option<ProcessRecorderRun> {
-        if command.is_empty() {
+    // Note: the argv in the launch info is run exactly as is, with the first item being the program to run.
+    pub fn new(launch_info: LaunchInfo, record_params: &ProcessRecordParams) -> Option<ProcessRecorderRun> {
+        if launch_info.argv.is_empty() || launch_info.argv[0].is_empty() {
             return None;
         }
 
-        Some(ProcessRecorderRun { record_params: record_params.clone(), command: command.to_string(), args,
-                                        core: ProcessRecorderCore::from_params(record_params), child_process: None })
+        let mut core = ProcessRecorderCore::from_params(record_params);
+        core.recording.launch_info = Some(launch_info.redacted(&record_params.env_redaction_patterns));
+
+        Some(ProcessRecorderRun { launch_info, output_capture: None, cgroup: None, terminate: TerminateParams::default(), core, watcher: None })
     }
 
-    // this is needed because we can't rely on psutil::process::Process::is_running() working
-    // on a forked/spawned process we started ourselves apparently.
-    fn check_process_is_running(&mut self) -> bool {
-        if self.child_process.is_none() {
-            return false;
+    pub fn set_output_capture(&mut self, output_capture: OutputCaptureParams) {
+        self.output_capture = Some(output_capture);
+    }
+
+    pub fn set_cgroup(&mut self, cgroup: CgroupParams) {
+        self.cgroup = Some(cgroup);
+    }
+
+    pub fn set_terminate_params(&mut self, terminate: TerminateParams) {
+        self.terminate = terminate;
+    }
+
+    // applies the terminate policy to the process after the recording was stopped (for the reason) before the process
+    // exited, and unless the policy is to leave it running, keeps recording it until it exits, so that the final samples
+    // and how it exited are still recorded. Pressing Ctrl+C again stops waiting (or kills it straight away with the
+    // terminate policy).
+    fn apply_terminate_policy(&mut self, stop_reason: StopReason, cancel_flag: &AtomicBool) {
+        let watcher = self.watcher.as_mut().unwrap();
+
+        let policy = self.terminate.policy;
+        match policy {
+            TerminatePolicy::Leave => {
+                eprintln!("{}, recording has stopped (process might continue running).", stop_reason.description());
+                return;
+            },
+            TerminatePolicy::Forward => {
+                let signal = if stop_reason == StopReason::Cancelled { TerminateSignal::Interrupt } else { TerminateSignal::Terminate };
+                eprintln!("{}, sending {} to the process and waiting for it to exit...", stop_reason.description(), signal.name());
+                watcher.send_signal(signal);
+            },
+            TerminatePolicy::Terminate => {
+                eprintln!("{}, terminating the process (SIGKILL after {:.1} secs)...", stop_reason.description(),
+                          self.terminate.grace_period.as_secs_f64());
+                watcher.send_signal(TerminateSignal::Terminate);
+            },
+            TerminatePolicy::Wait => {
+                eprintln!("{}, waiting for the process to exit (press Ctrl+C again to stop waiting)...", stop_reason.description());
+            },
         }
 
-        // check if the process has exited with an exit code.
-        // This does not block, so we can call it as a poll-like event loop
-        let still_running = match self.child_process.as_mut().unwrap().try_wait() {
-            Ok(Some(_status)) =>    false,
-            Ok(None) =>             true, // still running
-            Err(_err) =>            false, // not quite correct, but for the moment...
-        };
-        still_running
+        cancel_flag.store(false, Ordering::SeqCst);
+        let terminate_time = Instant::now();
+        let mut killed = false;
+
+        loop {
+            self.core.wait_for_next_sample();
+
+            let watcher = self.watcher.as_mut().unwrap();
+            if !watcher.is_running() {
+                break;
+            }
+
+            let latest_sample = self.core.record_samples();
+            self.core.update_sample_interval(latest_sample.as_ref());
+
+            let watcher = self.watcher.as_mut().unwrap();
+            let cancelled = cancel_flag.swap(false, Ordering::SeqCst);
+            if policy == TerminatePolicy::Terminate && !killed {
+                if cancelled || terminate_time.elapsed() >= self.terminate.grace_period {
+                    eprintln!("Killing the process (SIGKILL)...");
+                    watcher.send_signal(TerminateSignal::Kill);
+                    killed = true;
+                }
+            }
+            else if cancelled {
+                eprintln!("Stopped waiting for the process to exit (process might continue running).");
+                return;
+            }
+        }
+
+        self.core.finish_target_exited(self.watcher.as_ref().unwrap());
+    }
+
+    // kills the started process and removes its cgroup, when recording it couldn't be started.
+    fn abort_started_process(&mut self, cgroup: Option<&Cgroup>) {
+        if let Some(watcher) = self.watcher.as_mut() {
+            watcher.kill_and_reap();
+        }
+        if let Some(cgroup) = cgroup {
+            cgroup.remove();
+        }
     }
 }
 
 impl ProcessRecorder for ProcessRecorderRun {
-    fn start(&mut self, cancel_flag: Arc<AtomicBool>) -> bool {
+    fn start(&mut self, cancel_flag: Arc<AtomicBool>) -> Result<(), RecordError> {
         // spawn a forked process to run the process we're going to monitor in...
 
-        let mut command = Command::new(&self.command);
-        if let Some(args) = &self.args {
-            if !args.is_empty() {
-                command.args(args);
-            }
-        }
+        let mut command = self.launch_info.command();
 
-        let spawn_res = command.spawn();
+        // unless the process is going to be left running, start it in its own process group, so that signals (i.e. from
+        // Ctrl+C in the terminal) only go to psrec, which then handles the process according to the terminate policy.
+        let own_process_group = cfg!(unix) && self.terminate.policy != TerminatePolicy::Leave;
+        #[cfg(unix)]
+        if own_process_group {
+            use std::os::unix::process::CommandExt;
+            command.process_group(0);
+        }
 
-        if let Ok(child_info) = spawn_res {
-            let process = Process::new(child_info.id());
-            if let Err(err) = process {
-                eprintln!("Error recording (attaching to) spawned process: {}", err);
-                return false;
+        let mut output_capture = None;
+        if let Some(output_capture_params) = &self.output_capture {
+            output_capture = OutputCapture::new(output_capture_params.clone());
+            if output_capture.is_none() {
+                return Err(RecordError::Other);
             }
+            command.stdout(Stdio::piped());
+            command.stderr(Stdio::piped());
+        }
 
-            let mut recording_msg = format!("Recording samples every {} ", self.record_params.sample_interval_human);
-            if self.core.recorder_params.record_duration.is_none() {
-                recording_msg.push_str("until process ends...");
+        // if the cgroup can't be created, the process can still be recorded without it, unless limits or the parent
+        // cgroup were specified
+        let mut cgroup = None;
+        if let Some(cgroup_params) = &self.cgroup {
+            cgroup = Cgroup::create(cgroup_params);
+            if let Some(cgroup) = &cgroup {
+                eprintln!("Starting process in cgroup: {}", cgroup.path());
+                cgroup.add_to_command(&mut command);
             }
-            else {
-                recording_msg.push_str(&format!("for a duration of {}...", self.core.recorder_params.record_duration_human));
+            else if cgroup_params.is_required() {
+                return Err(RecordError::Other);
+            }
+        }
+
+        let process_start_time = Instant::now();
+        let mut child_info = match command.spawn() {
+            Ok(child_info) => child_info,
+            Err(err) => {
+                eprintln!("Error starting process: '{}'... {}", self.launch_info.argv_string(), err);
+                if let Some(cgroup) = &cgroup {
+                    cgroup.remove();
+                }
+                if err.kind() == std::io::ErrorKind::NotFound {
+                    return Err(RecordError::CommandNotFound);
+                }
+                return Err(RecordError::CommandNotExecutable);
             }
+        };
 
-            eprintln!("Successfully started process (PID: {}).\n{}", child_info.id(),
-                        recording_msg);
+        let child_stdout = child_info.stdout.take();
+        let child_stderr = child_info.stderr.take();
+        let child_pid = child_info.id();
 
-            self.child_process = Some(child_info);
+        self.watcher = Some(ChildProcessWatcher::new(child_info, process_start_time, own_process_group));
 
-            self.core.start_time = Some(SystemTime::now());
+        let process = Process::new(child_pid);
+        if let Err(err) = process {
+            eprintln!("Error recording (attaching to) spawned process: {}", err);
+            self.abort_started_process(cgroup.as_ref());
+            return Err(RecordError::AttachFailed);
+        }
+        let process = process.unwrap();
 
-            self.core.process = Some(process.unwrap());
+        eprintln!("Successfully started process (PID: {}).\n{}", child_pid,
+                    self.core.recording_description());
 
-            if !self.core.init_sampler() {
-                eprintln!("Error initialising process sampler.");
-                return false;
+        self.core.start_recording_timer();
+
+        // output lines are timestamped on the same time base as the samples
+        if let Some(output_capture) = output_capture.as_mut() {
+            let start_time = self.core.start_time.unwrap();
+            if let Some(child_stdout) = child_stdout {
+                output_capture.capture(child_stdout, OutputStream::Stdout, start_time);
+            }
+            if let Some(child_stderr) = child_stderr {
+                output_capture.capture(child_stderr, OutputStream::Stderr, start_time);
             }
+        }
 
-            self.core.record_sample();
+        let name = process.name().unwrap_or_else(|_| self.launch_info.argv[0].clone());
+        self.core.add_process(process, &name);
 
-            let sleep_duration = std::time::Duration::from_millis(self.record_params.sample_interval);
+        if !self.core.init_samplers() {
+            eprintln!("Error initialising process sampler.");
+            self.abort_started_process(cgroup.as_ref());
+            return Err(RecordError::SamplerInitFailed);
+        }
 
-            std::thread::sleep(sleep_duration);
+        // record the cgroup as a whole instead of just the process
+        if let Some(cgroup) = &cgroup {
+            let Some(sampler) = ProcessSamplerCgroup::new(cgroup.dir().to_path_buf()) else {
+                self.abort_started_process(Some(cgroup));
+                return Err(RecordError::SamplerInitFailed);
+            };
+            self.core.processes[0].sampler = Some(Box::new(sampler));
+        }
 
-            // this is a little bit silly, but we only want to pay the overhead if strictly necessary, so split the code
-            // paths so we're ultra-efficient while recording...
-            if let Some(record_duration_limit) = self.core.recorder_params.record_duration {
-                // we have a duration limit, so...
-                let duration_limit_ms = record_duration_limit as f64;
+        let stop_conditions = self.core.stop_conditions(cancel_flag.clone());
+        let stop_reason = self.core.record_until_stopped(self.watcher.as_mut().unwrap(), stop_conditions);
+        if stop_reason != StopReason::TargetExited {
+            self.apply_terminate_policy(stop_reason, &cancel_flag);
+        }
 
-                while self.check_process_is_running() {
-                    self.core.record_sample();
+        if let Some(output_capture) = output_capture {
+            let (output_lines, dropped_count) = output_capture.finish();
+            if dropped_count > 0 {
+                eprintln!("Warning: only the last {} lines of output were kept in the recording ({} earlier lines were dropped).",
+                          output_lines.len(), dropped_count);
+            }
+            self.core.recording.output_lines = output_lines;
+            self.core.recording.output_lines_dropped = dropped_count;
+        }
 
-                    if cancel_flag.load(Ordering::SeqCst) {
-                        eprintln!("Manually cancelled, recording has stopped (process might continue running).");
-                        return true;
-                    }
-    
-                    // TODO: this suffers from a tiny bit of drift...
-                    std::thread::sleep(sleep_duration);
-
-                    // TODO: error handling...
-                    let start_time = self.core.start_time.as_ref().unwrap();
-                    let elapsed_time = start_time.elapsed();
-                    // TODO: error handling...
-                    if elapsed_time.unwrap().as_secs_f64() >= duration_limit_ms {
-                        eprintln!("Recording duration limit reached, recording has stopped (process might continue running).");
-                        return true;
-                    }
-                }
+        if let Some(cgroup) = cgroup {
+            self.core.recording.cgroup_info = Some(cgroup.info());
+            // the cgroup can't be removed while the process is still running in it (i.e. if it was left running)
+            if self.watcher.as_mut().unwrap().is_running() {
+                eprintln!("The process is still running in cgroup: '{}', so it hasn't been removed.", cgroup.path());
             }
             else {
-                // we have no duration limit, so just do the basic stuff...
-
-                while self.check_process_is_running() {
-                    self.core.record_sample();
-
-                    if cancel_flag.load(Ordering::SeqCst) {
-                        eprintln!("Manually cancelled, recording has stopped (process might continue running).");
-                        return true;
-                    }
-    
-                    // TODO: this suffers from a tiny bit of drift...
-                    std::thread::sleep(sleep_duration);
-                }
+                cgroup.remove();
             }
-            
-            return true;
         }
 
-        // otherwise, spawning the task likely failed...
-        eprintln!("Error starting process: '{}'...", &self.command);
-
-        false
+        Ok(())
     }
 
-    // TODO: get rid of the need to do this with a copy...
     fn get_recording(&self) -> ProcessRecording {
-        self.core.recording.clone()
+        self.core.recording()
     }
 }
@@ -121,7 +121,7 @@ impl ProcessSampler for ProcessSamplerAdvanced {
         self.last_cstime = stat.cstime as u64;
 
         // set 0.0 as the time, it will be replaced later...
-        let new_sample = Sample { elapsed_time: 0.0, cpu_usage: cpu_usage as f32, curr_rss: full_rss, thread_count };
+        let new_sample = Sample { elapsed_time: 0.0, cpu_usage: cpu_usage as f32, curr_rss: full_rss, thread_count, oom_score: 0, bucket: None };
         Some(new_sample)
     }
 }
\ No newline at end of file
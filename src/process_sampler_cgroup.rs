@@ -0,0 +1,88 @@
+/*
+ psrec
+ Copyright 2022-2024 Peter Pearson.
+ Licensed under the Apache License, Version 2.0 (the "License");
+ You may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+ http://www.apache.org/licenses/LICENSE-2.0
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+ ---------
+*/
+
+use crate::cgroup::read_pids;
+use crate::oom_monitor::read_key_value;
+use crate::process_sampler::ProcessSampler;
+use crate::process_samples::Sample;
+
+use std::path::PathBuf;
+use std::time::Instant;
+
+// Note: this samples all the processes in a cgroup (cgroups v2 only) as a whole, so it includes all the descendants
+//       of the started process, even those which have been re-parented. The CPU usage comes from the cgroup's
+//       cpu.stat (so includes processes which have exited since the last sample), and the RSS and thread count are
+//       the totals of the processes currently in the cgroup.
+
+pub struct ProcessSamplerCgroup {
+    dir:                PathBuf,
+
+    last_usage_usec:    u64,
+    last_time_instant:  Instant,
+}
+
+impl ProcessSamplerCgroup {
+    pub fn new(dir: PathBuf) -> Option<ProcessSamplerCgroup> {
+        let Some(usage_usec) = read_cpu_usage_usec(&dir) else {
+            eprintln!("Error reading cpu.stat of cgroup: '{}'", dir.display());
+            return None;
+        };
+
+        Some(ProcessSamplerCgroup { dir, last_usage_usec: usage_usec, last_time_instant: Instant::now() })
+    }
+}
+
+impl ProcessSampler for ProcessSamplerCgroup {
+    fn get_sample(&mut self) -> Option<Sample> {
+        let usage_usec = read_cpu_usage_usec(&self.dir)?;
+        let instant = Instant::now();
+
+        let elapsed = instant.duration_since(self.last_time_instant).as_secs_f64();
+        if elapsed == 0.0 {
+            return None;
+        }
+
+        // absolute CPU usage, i.e. one full thread is 100.0
+        let cpu_usage = 100.0 * (usage_usec.saturating_sub(self.last_usage_usec) as f64 / 1000000.0) / elapsed;
+
+        let mut curr_rss = 0;
+        let mut thread_count = 0;
+        for pid in read_pids(&self.dir) {
+            // the process might have exited since the PIDs were read
+            let Ok(status) = std::fs::read_to_string(format!("/proc/{}/status", pid)) else {
+                continue;
+            };
+            for line in status.lines() {
+                if let Some(value) = line.strip_prefix("VmRSS:") {
+                    curr_rss += value.trim().trim_end_matches("kB").trim().parse::<u64>().unwrap_or(0) * 1024;
+                }
+                else if let Some(value) = line.strip_prefix("Threads:") {
+                    thread_count += value.trim().parse::<u32>().unwrap_or(0);
+                }
+            }
+        }
+
+        self.last_usage_usec = usage_usec;
+        self.last_time_instant = instant;
+
+        // set 0.0 as the time, it will be replaced later...
+        Some(Sample { elapsed_time: 0.0, cpu_usage: cpu_usage as f32, curr_rss, thread_count, oom_score: 0, bucket: None })
+    }
+}
+
+fn read_cpu_usage_usec(dir: &std::path::Path) -> Option<u64> {
+    let cpu_stat = std::fs::read_to_string(dir.join("cpu.stat")).ok()?;
+    read_key_value(&cpu_stat, "usage_usec")
+}
@@ -13,18 +13,141 @@
  ---------
 */
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::{fs::File, io::BufWriter, io::Write};
 
-use chrono::{Local, DateTime};
+use chrono::{Local, DateTime, SecondsFormat, Utc};
 
+use crate::cgroup::CgroupInfo;
+use crate::exit_info::ExitInfo;
+use crate::host_info::HostInfo;
+use crate::launch_info::LaunchInfo;
+use crate::oom_monitor::OomInfo;
+use crate::resource_limits::ResourceLimitHit;
+use crate::output_capture::OutputLine;
+use crate::process_follower::PidSwitch;
 use crate::process_recorder::ProcessRecordParams;
+use crate::sample_scheduler::SchedulerStats;
+use crate::sample_trigger::TriggerEvent;
+use crate::stop_conditions::StopReason;
+use crate::target_info::TargetInfo;
+use crate::utils::{default_time_precision, escape_metadata_value};
+
+// the format of absolute (wallclock) timestamps written for each sample
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimestampFormat {
+    // ISO 8601 / RFC 3339 date and time strings, with milliseconds
+    Iso8601,
+    // seconds since the Unix epoch, with milliseconds
+    Epoch,
+}
+
+impl TimestampFormat {
+    pub fn from_string(str_val: &str) -> Option<TimestampFormat> {
+        match str_val.to_ascii_lowercase().as_str() {
+            "iso" | "iso8601" =>    Some(TimestampFormat::Iso8601),
+            "epoch" | "unix" =>     Some(TimestampFormat::Epoch),
+            _ =>                    None,
+        }
+    }
+}
+
+// how recordings with multiple series are laid out when exported
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SeriesLayout {
+    // one row per sample time, with columns for the values of each series
+    #[default]
+    Wide,
+    // one row per sample of each series, with columns for the series name and PID
+    Long,
+}
+
+impl SeriesLayout {
+    pub fn from_string(str_val: &str) -> Option<SeriesLayout> {
+        match str_val.to_ascii_lowercase().as_str() {
+            "wide" =>   Some(SeriesLayout::Wide),
+            "long" =>   Some(SeriesLayout::Long),
+            _ =>        None,
+        }
+    }
+}
+
+// params controlling how a recording is viewed/exported, as opposed to how it's recorded.
+#[derive(Clone, Debug, Default)]
+pub struct ExportParams {
+    // whether to write metadata about the recording as comments (where the file format needs that)
+    pub add_metadata_comments:  bool,
+
+    // whether to 'normalise' CPU usage values (out of 100%) to a thread count, instead of writing absolute values.
+    pub normalise_cpu_usage:    bool,
+
+    // thread count to normalise against, overriding the system thread count stored in the recording.
+    pub normalise_thread_count: Option<u32>,
+
+    // if set, an additional column of absolute (wallclock) timestamps is written in this format for each sample.
+    pub timestamp_format:       Option<TimestampFormat>,
+
+    // whether timestamps should be written in UTC, rather than the local timezone.
+    pub utc_timestamps:         bool,
+
+    // number of decimal places to write elapsed times with. By default this depends on the sample interval.
+    pub time_precision:         Option<usize>,
+
+    // how to lay out recordings with multiple series
+    pub series_layout:          SeriesLayout,
+
+    // whether to write an additional 'total' series, aggregating the values of all the series, for recordings
+    // with multiple series
+    pub aggregate_series:       bool,
+}
+
+impl ExportParams {
+    pub fn new(add_metadata_comments: bool) -> ExportParams {
+        ExportParams { add_metadata_comments, ..Default::default() }
+    }
+
+    // returns the thread count to normalise against, or None if the values should be absolute.
+    pub fn normalisation_thread_count(&self, recording: &ProcessRecording) -> Option<u32> {
+        if !self.normalise_cpu_usage {
+            return None;
+        }
+
+        Some(self.normalise_thread_count.unwrap_or(recording.num_system_threads).max(1))
+    }
+
+    // returns the number of decimal places elapsed times should be written with for the recording.
+    pub fn time_precision(&self, recording: &ProcessRecording) -> usize {
+        self.time_precision.unwrap_or_else(|| default_time_precision(recording.sample_interval))
+    }
+
+    // formats the timestamp as ISO 8601 (regardless of the sample timestamp format), in UTC or local time as configured.
+    pub fn format_timestamp(&self, timestamp: &DateTime<Local>) -> String {
+        if self.utc_timestamps {
+            timestamp.with_timezone(&Utc).to_rfc3339_opts(SecondsFormat::Millis, true)
+        }
+        else {
+            timestamp.to_rfc3339_opts(SecondsFormat::Millis, false)
+        }
+    }
+
+    // returns the absolute timestamp of a sample from the start timestamp of the recording and the sample's elapsed time,
+    // formatted in the configured timestamp format.
+    pub fn format_sample_timestamp(&self, start_timestamp: &DateTime<Local>, elapsed_time: f64) -> String {
+        let timestamp = *start_timestamp + chrono::Duration::microseconds((elapsed_time * 1_000_000.0) as i64);
+        match self.timestamp_format {
+            Some(TimestampFormat::Epoch) => format!("{:.3}", timestamp.timestamp_millis() as f64 / 1000.0),
+            _ =>                            self.format_timestamp(&timestamp),
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct Sample {
-    // in seconds
-    pub elapsed_time:       f32,
+    // in seconds, since the start of the recording (from a monotonic clock, so not affected by wallclock changes)
+    pub elapsed_time:       f64,
 
-    // Note: this value may or may not be normalised (to 100.0 if so), depending on the recording params
+    // Note: this value is always absolute (i.e. one full thread is 100.0, four threads is 400.0), and is only
+    //       normalised on export (or when printed live) if requested, via the ExportParams.
     pub cpu_usage:          f32,
 
     // in bytes
@@ -33,28 +156,183 @@ pub struct Sample {
     // number of process threads
     pub thread_count:       u32,
 
+    // the 'badness' score of the process the kernel OOM killer would use (Linux only)
+    pub oom_score:          i32,
+
+    // if the sample is the result of downsampling multiple samples, the range of values of them
+    pub bucket:             Option<SampleBucket>,
+
 //    pub peak_rss:           u64,
 }
 
+// the range of values of the samples a downsampled sample was made from. The values of the sample itself are
+// the means of them (apart from the thread count and OOM score, which are the max), and its elapsed time is the
+// time of the first of them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SampleBucket {
+    // number of original samples
+    pub count:              u32,
+
+    pub min_cpu_usage:      f32,
+    pub max_cpu_usage:      f32,
+
+    pub min_rss:            u64,
+    pub max_rss:            u64,
+}
+
+impl Sample {
+    // a sample with just the values most tests care about set, for the tests of all the modules which use samples
+    #[cfg(test)]
+    pub fn for_test(elapsed_time: f64, cpu_usage: f32, curr_rss: u64) -> Sample {
+        Sample { elapsed_time, cpu_usage, curr_rss, thread_count: 1, oom_score: 0, bucket: None }
+    }
+
+    // adds the values of the other sample to this one (apart from the OOM score, where the max is kept),
+    // for aggregating the samples of multiple processes.
+    pub fn accumulate(&mut self, other: &Sample) {
+        if self.bucket.is_some() || other.bucket.is_some() {
+            let (min_cpu_usage, max_cpu_usage) = self.cpu_usage_range();
+            let (other_min_cpu_usage, other_max_cpu_usage) = other.cpu_usage_range();
+            let (min_rss, max_rss) = self.rss_range();
+            let (other_min_rss, other_max_rss) = other.rss_range();
+            self.bucket = Some(SampleBucket { count: self.sample_count().max(other.sample_count()),
+                                              min_cpu_usage: min_cpu_usage + other_min_cpu_usage,
+                                              max_cpu_usage: max_cpu_usage + other_max_cpu_usage,
+                                              min_rss: min_rss + other_min_rss,
+                                              max_rss: max_rss + other_max_rss });
+        }
+
+        self.cpu_usage += other.cpu_usage;
+        self.curr_rss += other.curr_rss;
+        self.thread_count += other.thread_count;
+        self.oom_score = self.oom_score.max(other.oom_score);
+    }
+
+    // merges the (later) other sample into this one when downsampling, with the values being the means of
+    // all the original samples, weighted by the number of them each sample was made from.
+    pub fn merge(&mut self, other: &Sample) {
+        let count = self.sample_count();
+        let other_count = other.sample_count();
+        let total_count = count + other_count;
+
+        let (min_cpu_usage, max_cpu_usage) = self.cpu_usage_range();
+        let (other_min_cpu_usage, other_max_cpu_usage) = other.cpu_usage_range();
+        let (min_rss, max_rss) = self.rss_range();
+        let (other_min_rss, other_max_rss) = other.rss_range();
+        self.bucket = Some(SampleBucket { count: total_count,
+                                          min_cpu_usage: min_cpu_usage.min(other_min_cpu_usage),
+                                          max_cpu_usage: max_cpu_usage.max(other_max_cpu_usage),
+                                          min_rss: min_rss.min(other_min_rss),
+                                          max_rss: max_rss.max(other_max_rss) });
+
+        self.cpu_usage = (self.cpu_usage * count as f32 + other.cpu_usage * other_count as f32) / total_count as f32;
+        self.curr_rss = ((self.curr_rss as u128 * count as u128 + other.curr_rss as u128 * other_count as u128) / total_count as u128) as u64;
+        self.thread_count = self.thread_count.max(other.thread_count);
+        self.oom_score = self.oom_score.max(other.oom_score);
+    }
+
+    // the number of original samples this sample represents
+    pub fn sample_count(&self) -> u32 {
+        self.bucket.map_or(1, |bucket| bucket.count)
+    }
+
+    pub fn cpu_usage_range(&self) -> (f32, f32) {
+        self.bucket.map_or((self.cpu_usage, self.cpu_usage), |bucket| (bucket.min_cpu_usage, bucket.max_cpu_usage))
+    }
+
+    pub fn rss_range(&self) -> (u64, u64) {
+        self.bucket.map_or((self.curr_rss, self.curr_rss), |bucket| (bucket.min_rss, bucket.max_rss))
+    }
+}
+
+// a named series of samples of a single process. Recordings of multiple processes have one series per process,
+// all sampled on the common timeline of the recording (so samples taken at the same time have identical elapsed times).
+#[derive(Clone, Debug)]
+pub struct SampleSeries {
+    pub name:           String,
+
+    pub pid:            u32,
+
+    pub samples:        Vec<Sample>,
+}
+
+impl SampleSeries {
+    pub fn new(name: &str, pid: u32) -> SampleSeries {
+        SampleSeries { name: name.to_string(), pid, samples: Vec::with_capacity(512) }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ProcessRecording {
 
     // start timestamp
     pub start_timestamp:        DateTime<Local>,
 
-    pub normalised_cpu_usage:   bool,
-
     // whether we're recording the number of process threads...
     pub have_thread_counts:     bool,
 
+    // whether we're recording the OOM score of the process...
+    pub have_oom_scores:        bool,
+
+    // in ms
+    pub sample_interval:        u64,
+    // in ms, if the sample interval was adaptive (in which case the above is the min interval)
+    pub max_sample_interval:    Option<u64>,
+
+    // the max number of samples to keep per series, before older samples are downsampled
+    pub max_samples:            Option<usize>,
+
+    // whether any samples have been downsampled, in which case the ranges of their values are also exported
+    pub downsampled:            bool,
+
     pub initial_process_id:     u32,
     pub current_process_id:     u32,
 
-    // used for normalising CPU usage if needed... (depending on configuration)
+    // the thread count of the system at the point of recording, used for normalising CPU usage on export if needed...
     // Note: this functionality is only useful in the most basic scenarios...
     pub num_system_threads:     u32,
 
-    pub samples:        Vec<Sample>,
+    // info about the machine the recording was made on
+    pub host_info:              HostInfo,
+
+    // identifying info about the process being recorded
+    pub target_info:            Option<TargetInfo>,
+
+    // how the process was launched (only available for processes we started ourselves)
+    pub launch_info:            Option<LaunchInfo>,
+
+    // how the process exited, and its final resource usage (only available for processes we started ourselves)
+    pub exit_info:              Option<ExitInfo>,
+
+    // whether the process was killed by the OOM killer (only available once the process has exited)
+    pub oom_info:               Option<OomInfo>,
+
+    // the resource limit the process likely died from hitting, if it was started with any
+    pub limit_hit:              Option<ResourceLimitHit>,
+
+    // the totals of the cgroup the process was started in (if it was), including all its descendants
+    pub cgroup_info:            Option<CgroupInfo>,
+
+    // stats about how accurately the samples were taken at their scheduled times
+    pub scheduler_stats:        Option<SchedulerStats>,
+
+    // why the recording stopped
+    pub stop_reason:            Option<StopReason>,
+
+    // switches of the recorded process to its replacement (i.e. when it was restarted), when following restarts
+    pub pid_switches:           Vec<PidSwitch>,
+
+    // when capturing samples was triggered, in trigger mode (where only the samples around triggers are kept)
+    pub trigger_events:         Vec<TriggerEvent>,
+
+    // the captured stdout/stderr lines of the process (only available for processes we started ourselves)
+    pub output_lines:           Vec<OutputLine>,
+
+    // the number of (older) captured lines which were dropped to keep within the max number of lines
+    pub output_lines_dropped:   u64,
+
+    // one series per process being recorded
+    pub series:                 Vec<SampleSeries>,
 }
 
 impl ProcessRecording {
@@ -67,50 +345,440 @@ impl ProcessRecording {
             num_threads = nt.get() as u32;
         }
         ProcessRecording { start_timestamp: Local::now(),
-                           normalised_cpu_usage: recorder_params.normalise_cpu_usage,
                            have_thread_counts: recorder_params.record_thread_count,
+                           have_oom_scores: recorder_params.record_oom_score,
+                           sample_interval: recorder_params.sample_interval,
+                           max_sample_interval: recorder_params.adaptive_interval.as_ref().map(|adaptive_interval| adaptive_interval.max_interval.as_millis() as u64),
+                           max_samples: recorder_params.max_samples,
+                           downsampled: false,
                            initial_process_id,
                            current_process_id: initial_process_id,
                            num_system_threads: num_threads,
-                           samples: Vec::with_capacity(512) }
+                           host_info: recorder_params.host_info.clone(),
+                           target_info: None,
+                           launch_info: None,
+                           exit_info: None,
+                           oom_info: None,
+                           limit_hit: None,
+                           cgroup_info: None,
+                           scheduler_stats: None,
+                           stop_reason: None,
+                           pid_switches: Vec::new(),
+                           trigger_events: Vec::new(),
+                           output_lines: Vec::new(),
+                           output_lines_dropped: 0,
+                           series: Vec::new() }
     }
 
-    // TODO: use Result properly for return code...
-    pub fn save_to_csv_file(&self, output_file_path: &str, add_metadata_comments: bool) -> bool {
-        let file = File::create(output_file_path);
-        if file.is_err() {
-            eprintln!("Error saving results to CSV file: {}", output_file_path);
-            return false;
-        }
-        let mut buf_writer = BufWriter::new(file.unwrap());
+    pub fn set_target_info(&mut self, target_info: TargetInfo) {
+        self.initial_process_id = target_info.pid;
+        self.current_process_id = target_info.pid;
+        self.target_info = Some(target_info);
+    }
 
-        if add_metadata_comments {
-            writeln!(buf_writer, "# Process recording.").unwrap();
+    // adds a new (empty) series, returning the index of it
+    pub fn add_series(&mut self, name: &str, pid: u32) -> usize {
+        self.series.push(SampleSeries::new(name, pid));
+        self.series.len() - 1
+    }
 
-            if self.have_thread_counts {
-                writeln!(buf_writer, "# Time elapsed,CPU Usage,RSS,Thread Count").unwrap();
+    // returns a series of the totals of the values of all the series at each sample time (with the max of the OOM scores).
+    pub fn aggregate_series(&self) -> SampleSeries {
+        let mut total_series = SampleSeries::new("total", 0);
+        for (elapsed_time, samples) in Self::timeline_rows(&self.series.iter().collect::<Vec<_>>()) {
+            let mut total = Sample { elapsed_time, cpu_usage: 0.0, curr_rss: 0, thread_count: 0, oom_score: 0, bucket: None };
+            for sample in samples.into_iter().flatten() {
+                total.accumulate(sample);
             }
-            else {
-                writeln!(buf_writer, "# Time elapsed,CPU Usage,RSS").unwrap();
+            total_series.samples.push(total);
+        }
+        total_series
+    }
+
+    // returns the samples of the series grouped by sample time, in time order, with the sample of each series
+    // at that time (if there is one, as processes can exit or fail to be sampled).
+    fn timeline_rows<'a>(series: &[&'a SampleSeries]) -> Vec<(f64, Vec<Option<&'a Sample>>)> {
+        // Note: elapsed times are never negative, so the ordering of the bit patterns matches the ordering of the values.
+        let mut rows: BTreeMap<u64, Vec<Option<&Sample>>> = BTreeMap::new();
+        for (series_index, single_series) in series.iter().enumerate() {
+            for sample in &single_series.samples {
+                let row = rows.entry(sample.elapsed_time.to_bits()).or_insert_with(|| vec![None; series.len()]);
+                row[series_index] = Some(sample);
             }
+        }
+
+        rows.into_iter().map(|(time_bits, samples)| (f64::from_bits(time_bits), samples)).collect()
+    }
+
+    // halves the resolution of the samples of all the series before the elapsed time, by merging adjacent pairs of
+    // sample times on the timeline shared by the series, so that the merged samples of each series still line up (even
+    // if some series are missing samples at some times). Doing this repeatedly as the recording grows means older
+    // samples are progressively downsampled more.
+    pub fn downsample_before(&mut self, end_time: f64) {
+        // Note: elapsed times are never negative, so the ordering of the bit patterns matches the ordering of the values.
+        let times: Vec<f64> = self.series.iter().flat_map(|single_series| &single_series.samples)
+                                             .filter(|sample| sample.elapsed_time < end_time)
+                                             .map(|sample| sample.elapsed_time.to_bits())
+                                             .collect::<BTreeSet<u64>>().into_iter().map(f64::from_bits).collect();
+
+        for single_series in &mut self.series {
+            let count = single_series.samples.partition_point(|sample| sample.elapsed_time < end_time);
+            // the index of the pair of times each merged sample is for, along with the merged sample
+            let mut merged: Vec<(usize, Sample)> = Vec::with_capacity(count / 2 + 1);
+            for sample in &single_series.samples[..count] {
+                let pair_index = times.partition_point(|time| *time < sample.elapsed_time) / 2;
+                match merged.last_mut() {
+                    Some((last_pair_index, merged_sample)) if *last_pair_index == pair_index => merged_sample.merge(sample),
+                    _ => {
+                        // the merged sample is at the time of the first of the pair, whether or not this series has a sample then
+                        let mut merged_sample = sample.clone();
+                        merged_sample.elapsed_time = times[pair_index * 2];
+                        merged.push((pair_index, merged_sample));
+                    }
+                }
+            }
+
+            single_series.samples.splice(..count, merged.into_iter().map(|(_, sample)| sample));
+        }
+    }
+
+    // returns the cpu usage value of the sample, either as the absolute value recorded, or normalised
+    // to the provided thread count.
+    pub fn cpu_usage_value(sample: &Sample, normalise_thread_count: Option<u32>) -> f32 {
+        if let Some(thread_count) = normalise_thread_count {
+            sample.cpu_usage / thread_count as f32
+        }
+        else {
+            sample.cpu_usage
+        }
+    }
+
+    // returns the metadata about the recording as key/value string pairs, as written to the CSV file comments.
+    pub fn metadata_items(&self, export_params: &ExportParams) -> Vec<(&'static str, String)> {
+        let normalise_thread_count = export_params.normalisation_thread_count(self);
+
+        let mut items = vec![("cputype", if normalise_thread_count.is_some() { "normalised" } else { "absolute" }.to_string()),
+                             ("systhreads", self.num_system_threads.to_string())];
+        if let Some(thread_count) = normalise_thread_count {
+            // write this out explicitly, as it might not be the same as the system thread count
+            items.push(("normthreads", thread_count.to_string()));
+        }
+
+        items.push(("starttime", export_params.format_timestamp(&self.start_timestamp)));
+        items.push(("intervalms", self.sample_interval.to_string()));
+        if let Some(max_sample_interval) = self.max_sample_interval {
+            items.push(("maxintervalms", max_sample_interval.to_string()));
+        }
+        if let Some(max_samples) = self.max_samples {
+            items.push(("maxsamples", max_samples.to_string()));
+        }
+
+        if let Some(stop_reason) = &self.stop_reason {
+            items.push(("stopreason", stop_reason.name().to_string()));
+        }
+
+        let time_precision = export_params.time_precision(self);
+        for pid_switch in &self.pid_switches {
+            items.push(("pidswitch", pid_switch.metadata_value(time_precision)));
+        }
+        for trigger_event in &self.trigger_events {
+            items.push(("trigger", trigger_event.metadata_value(time_precision)));
+        }
+        if self.output_lines_dropped > 0 {
+            items.push(("outputdropped", self.output_lines_dropped.to_string()));
+        }
+        for output_line in &self.output_lines {
+            items.push(("output", output_line.metadata_value(time_precision)));
+        }
+
+        if self.series.len() > 1 {
+            let series: Vec<String> = self.series.iter().map(|series| format!("{}:{}", series.name, series.pid)).collect();
+            items.push(("series", series.join(",")));
+        }
+
+        items.extend(self.host_info.metadata_items());
+
+        if let Some(target_info) = &self.target_info {
+            items.extend(target_info.metadata_items());
+        }
+
+        if let Some(launch_info) = &self.launch_info {
+            items.extend(launch_info.metadata_items());
+        }
+
+        if let Some(exit_info) = &self.exit_info {
+            items.extend(exit_info.metadata_items());
+        }
+
+        if let Some(oom_info) = &self.oom_info {
+            items.extend(oom_info.metadata_items());
+        }
+
+        if let Some(limit_hit) = &self.limit_hit {
+            items.push(("limithit", limit_hit.metadata_value()));
+        }
+
+        if let Some(cgroup_info) = &self.cgroup_info {
+            items.extend(cgroup_info.metadata_items());
+        }
+
+        if let Some(scheduler_stats) = &self.scheduler_stats {
+            items.extend(scheduler_stats.metadata_items());
+        }
+
+        items
+    }
 
-            writeln!(buf_writer, "#@ cputype: {}", if self.normalised_cpu_usage { "normalised" } else { "absolute" }).unwrap();
-            writeln!(buf_writer, "#@ systhreads: {}", self.num_system_threads).unwrap();
+    // returns the aggregate 'total' series to export in addition to the recorded series, if requested.
+    fn export_total_series(&self, export_params: &ExportParams) -> Option<SampleSeries> {
+        if export_params.aggregate_series && self.series.len() > 1 {
+            Some(self.aggregate_series())
         }
+        else {
+            None
+        }
+    }
 
+    // returns the names of the value columns for a single sample, which depend on what was recorded.
+    fn value_column_names(&self) -> Vec<&'static str> {
+        let mut columns = vec!["CPU Usage", "RSS"];
         if self.have_thread_counts {
-            for sample in &self.samples {
-                writeln!(buf_writer, "{:.1},{:.1},{},{}", sample.elapsed_time, sample.cpu_usage, sample.curr_rss, sample.thread_count).unwrap();
-            }
+            columns.push("Thread Count");
+        }
+        if self.have_oom_scores {
+            columns.push("OOM Score");
+        }
+        if self.downsampled {
+            columns.extend(["CPU Usage Min", "CPU Usage Max", "RSS Min", "RSS Max"]);
+        }
+        columns
+    }
+
+    // returns the names of the columns written for each row, which depend on the recording, series and export params.
+    fn column_names(&self, export_params: &ExportParams, series: &[&SampleSeries]) -> Vec<String> {
+        let mut columns = vec!["Time elapsed".to_string()];
+        if export_params.timestamp_format.is_some() {
+            columns.push("Timestamp".to_string());
+        }
+
+        let value_columns = self.value_column_names();
+        if export_params.series_layout == SeriesLayout::Long {
+            columns.extend(["Series".to_string(), "PID".to_string()]);
+            columns.extend(value_columns.iter().map(|column| column.to_string()));
+        }
+        else if series.len() == 1 {
+            columns.extend(value_columns.iter().map(|column| column.to_string()));
         }
         else {
-            for sample in &self.samples {
-                writeln!(buf_writer, "{:.1},{:.1},{}", sample.elapsed_time, sample.cpu_usage, sample.curr_rss).unwrap();
+            // prefix the value columns with the name of each series
+            for single_series in series {
+                for column in &value_columns {
+                    columns.push(format!("{} {}", single_series.name, column));
+                }
+            }
+        }
+        columns
+    }
+
+    // writes the elapsed time (and timestamp if needed) values of a row to the string.
+    fn write_time_values(&self, elapsed_time: f64, export_params: &ExportParams, time_precision: usize, output: &mut String) {
+        use std::fmt::Write;
+
+        write!(output, "{:.*}", time_precision, elapsed_time).unwrap();
+        if export_params.timestamp_format.is_some() {
+            let timestamp = export_params.format_sample_timestamp(&self.start_timestamp, elapsed_time);
+            write!(output, ",{}", timestamp).unwrap();
+        }
+    }
+
+    // writes the values of the sample (each preceded by a separator) to the string, in the order of the columns returned
+    // from value_column_names(), or empty values if there is no sample.
+    fn write_sample_values(&self, sample: Option<&Sample>, normalise_thread_count: Option<u32>, output: &mut String) {
+        use std::fmt::Write;
+
+        let separator = ",";
+
+        let Some(sample) = sample else {
+            for _ in self.value_column_names() {
+                output.push_str(separator);
+            }
+            return;
+        };
+
+        write!(output, "{}{:.1}{}{}", separator, Self::cpu_usage_value(sample, normalise_thread_count), separator, sample.curr_rss).unwrap();
+        if self.have_thread_counts {
+            write!(output, "{}{}", separator, sample.thread_count).unwrap();
+        }
+        if self.have_oom_scores {
+            write!(output, "{}{}", separator, sample.oom_score).unwrap();
+        }
+        if self.downsampled {
+            let (min_cpu_usage, max_cpu_usage) = sample.cpu_usage_range();
+            let (min_rss, max_rss) = sample.rss_range();
+            let normalise = |cpu_usage: f32| normalise_thread_count.map_or(cpu_usage, |thread_count| cpu_usage / thread_count as f32);
+            write!(output, "{}{:.1}{}{:.1}{}{}{}{}", separator, normalise(min_cpu_usage), separator, normalise(max_cpu_usage),
+                   separator, min_rss, separator, max_rss).unwrap();
+        }
+    }
+
+    // writes each row of values of the series in the configured layout, in the order of the columns returned
+    // from column_names(), passing the string of each row to the provided function.
+    fn write_rows(&self, export_params: &ExportParams, series: &[&SampleSeries],
+                  mut write_row: impl FnMut(&str) -> std::io::Result<()>) -> std::io::Result<()> {
+        use std::fmt::Write;
+
+        let normalise_thread_count = export_params.normalisation_thread_count(self);
+        let time_precision = export_params.time_precision(self);
+        let separator = ",";
+
+        let mut line = String::new();
+        for (elapsed_time, samples) in Self::timeline_rows(series) {
+            if export_params.series_layout == SeriesLayout::Long {
+                for (single_series, sample) in series.iter().zip(samples) {
+                    if sample.is_none() {
+                        continue;
+                    }
+                    line.clear();
+                    self.write_time_values(elapsed_time, export_params, time_precision, &mut line);
+                    write!(line, "{}{}{}{}", separator, single_series.name, separator, single_series.pid).unwrap();
+                    self.write_sample_values(sample, normalise_thread_count, &mut line);
+                    write_row(&line)?;
+                }
+            }
+            else {
+                line.clear();
+                self.write_time_values(elapsed_time, export_params, time_precision, &mut line);
+                for sample in samples {
+                    self.write_sample_values(sample, normalise_thread_count, &mut line);
+                }
+                write_row(&line)?;
             }
         }
 
-        buf_writer.flush().unwrap();
+        Ok(())
+    }
+
+    pub fn save_to_csv_file(&self, output_file_path: &str, export_params: &ExportParams) -> bool {
+        let result = File::create(output_file_path).and_then(|file| self.write_csv_file(BufWriter::new(file), export_params));
+        if let Err(err) = result {
+            eprintln!("Error saving results to CSV file: {}, {}", output_file_path, err);
+            return false;
+        }
 
         true
     }
+
+    fn write_csv_file(&self, mut buf_writer: BufWriter<File>, export_params: &ExportParams) -> std::io::Result<()> {
+        let total_series = self.export_total_series(export_params);
+        let series: Vec<&SampleSeries> = self.series.iter().chain(total_series.iter()).collect();
+
+        if export_params.add_metadata_comments {
+            writeln!(buf_writer, "# Process recording.")?;
+
+            writeln!(buf_writer, "# {}", self.column_names(export_params, &series).join(","))?;
+
+            for (key, value) in self.metadata_items(export_params) {
+                writeln!(buf_writer, "#@ {}: {}", key, escape_metadata_value(&value))?;
+            }
+        }
+
+        self.write_rows(export_params, &series, |line| {
+            writeln!(buf_writer, "{}", line)
+        })?;
+
+        buf_writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aggregate_series() {
+        let mut recording = ProcessRecording::new(&ProcessRecordParams::new(None, None), 0);
+        let first = recording.add_series("first", 1);
+        let second = recording.add_series("second", 2);
+        recording.series[first].samples.extend([Sample::for_test(0.0, 10.0, 100), Sample::for_test(1.0, 20.0, 200), Sample::for_test(2.0, 30.0, 300)]);
+        // the second process was only sampled for the last two sample times
+        recording.series[second].samples.extend([Sample::for_test(1.0, 5.0, 50), Sample::for_test(2.0, 5.0, 50)]);
+
+        let total = recording.aggregate_series();
+        assert_eq!(total.name, "total");
+        let values: Vec<(f64, f32, u64, u32)> = total.samples.iter().map(|sample| (sample.elapsed_time, sample.cpu_usage, sample.curr_rss, sample.thread_count)).collect();
+        assert_eq!(values, vec![(0.0, 10.0, 100, 1), (1.0, 25.0, 250, 2), (2.0, 35.0, 350, 2)]);
+    }
+
+    #[test]
+    fn test_export_cpu_usage_values() {
+        let mut recording = ProcessRecording::new(&ProcessRecordParams::new(None, None), 0);
+        recording.num_system_threads = 4;
+        let index = recording.add_series("test", 1);
+        recording.series[index].samples.push(Sample::for_test(1.0, 200.0, 100));
+
+        let series: Vec<&SampleSeries> = recording.series.iter().collect();
+        let exported_rows = |export_params: &ExportParams| {
+            let mut rows = Vec::new();
+            recording.write_rows(export_params, &series, |line| { rows.push(line.to_string()); Ok(()) }).unwrap();
+            rows
+        };
+
+        // the recording keeps the absolute value, which is exported as is by default
+        let mut export_params = ExportParams::new(true);
+        assert_eq!(exported_rows(&export_params), vec!["1.0,200.0,100"]);
+        assert_eq!(recording.metadata_items(&export_params)[0], ("cputype", "absolute".to_string()));
+
+        // normalised to the system thread count of the recording
+        export_params.normalise_cpu_usage = true;
+        assert_eq!(exported_rows(&export_params), vec!["1.0,50.0,100"]);
+        assert_eq!(recording.metadata_items(&export_params)[0], ("cputype", "normalised".to_string()));
+
+        // re-normalised against a different thread count
+        export_params.normalise_thread_count = Some(8);
+        assert_eq!(exported_rows(&export_params), vec!["1.0,25.0,100"]);
+        assert!(recording.metadata_items(&export_params).contains(&("normthreads", "8".to_string())));
+    }
+
+    #[test]
+    fn test_downsample_before() {
+        let mut recording = ProcessRecording::new(&ProcessRecordParams::new(None, None), 0);
+        let index = recording.add_series("test", 1);
+        recording.series[index].samples.extend([Sample::for_test(0.0, 10.0, 100), Sample::for_test(1.0, 30.0, 300), Sample::for_test(2.0, 20.0, 200),
+                                                Sample::for_test(3.0, 40.0, 400), Sample::for_test(4.0, 50.0, 500)]);
+
+        recording.downsample_before(4.0);
+        let series = &recording.series[index];
+        let values: Vec<(f64, f32, u64)> = series.samples.iter().map(|sample| (sample.elapsed_time, sample.cpu_usage, sample.curr_rss)).collect();
+        assert_eq!(values, vec![(0.0, 20.0, 200), (2.0, 30.0, 300), (4.0, 50.0, 500)]);
+        assert_eq!(series.samples[0].bucket, Some(SampleBucket { count: 2, min_cpu_usage: 10.0, max_cpu_usage: 30.0, min_rss: 100, max_rss: 300 }));
+        assert_eq!(series.samples[2].bucket, None);
+
+        // merging already downsampled samples weights the means by the number of original samples
+        recording.downsample_before(5.0);
+        let series = &recording.series[index];
+        assert_eq!(series.samples.len(), 2);
+        assert_eq!(series.samples[0].cpu_usage, 25.0);
+        assert_eq!(series.samples[0].sample_count(), 4);
+        assert_eq!(series.samples[1].elapsed_time, 4.0);
+        assert_eq!(series.samples[1].cpu_usage, 50.0);
+        assert_eq!(series.samples[1].cpu_usage_range(), (50.0, 50.0));
+    }
+
+    #[test]
+    fn test_downsample_before_multiple_series() {
+        let mut recording = ProcessRecording::new(&ProcessRecordParams::new(None, None), 0);
+        let first = recording.add_series("first", 1);
+        let second = recording.add_series("second", 2);
+        recording.series[first].samples.extend([Sample::for_test(0.0, 10.0, 100), Sample::for_test(1.0, 30.0, 300), Sample::for_test(2.0, 20.0, 200),
+                                                Sample::for_test(3.0, 40.0, 400), Sample::for_test(4.0, 50.0, 500)]);
+        // the second process missed the sample at 0.0, and the one at 2.0
+        recording.series[second].samples.extend([Sample::for_test(1.0, 5.0, 50), Sample::for_test(3.0, 15.0, 150), Sample::for_test(4.0, 5.0, 50)]);
+
+        recording.downsample_before(4.0);
+        let first_times: Vec<f64> = recording.series[first].samples.iter().map(|sample| sample.elapsed_time).collect();
+        let second_values: Vec<(f64, f32, u32)> = recording.series[second].samples.iter().map(|sample| (sample.elapsed_time, sample.cpu_usage, sample.sample_count())).collect();
+        assert_eq!(first_times, vec![0.0, 2.0, 4.0]);
+        // the samples of the second series are merged into the same time buckets as the first
+        assert_eq!(second_values, vec![(0.0, 5.0, 1), (2.0, 15.0, 1), (4.0, 5.0, 1)]);
+    }
 }
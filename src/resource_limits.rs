@@ -0,0 +1,228 @@
+/*
+ psrec
+ Copyright 2022-2024 Peter Pearson.
+ Licensed under the Apache License, Version 2.0 (the "License");
+ You may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+ http://www.apache.org/licenses/LICENSE-2.0
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+ ---------
+*/
+
+use crate::exit_info::ExitInfo;
+use crate::oom_monitor::OomVerdict;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResourceLimitKind {
+    AddressSpace,
+    Data,
+    CpuTime,
+    OpenFiles,
+    CoreSize,
+}
+
+impl ResourceLimitKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ResourceLimitKind::AddressSpace =>  "as",
+            ResourceLimitKind::Data =>          "data",
+            ResourceLimitKind::CpuTime =>       "cpu",
+            ResourceLimitKind::OpenFiles =>     "nofile",
+            ResourceLimitKind::CoreSize =>      "core",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            ResourceLimitKind::AddressSpace =>  "address space",
+            ResourceLimitKind::Data =>          "data segment size",
+            ResourceLimitKind::CpuTime =>       "CPU time",
+            ResourceLimitKind::OpenFiles =>     "open files",
+            ResourceLimitKind::CoreSize =>      "core file size",
+        }
+    }
+
+    // returns the value of the limit formatted in its units
+    pub fn format_value(&self, value: u64) -> String {
+        match self {
+            ResourceLimitKind::CpuTime =>       format!("{} secs", value),
+            ResourceLimitKind::OpenFiles =>     value.to_string(),
+            _ =>                                format!("{} KB", value / 1024),
+        }
+    }
+
+    #[cfg(unix)]
+    fn resource(&self) -> libc::c_int {
+        // the type of the resource constants differs between platforms (and libc targets)
+        (match self {
+            ResourceLimitKind::AddressSpace =>  libc::RLIMIT_AS,
+            ResourceLimitKind::Data =>          libc::RLIMIT_DATA,
+            ResourceLimitKind::CpuTime =>       libc::RLIMIT_CPU,
+            ResourceLimitKind::OpenFiles =>     libc::RLIMIT_NOFILE,
+            ResourceLimitKind::CoreSize =>      libc::RLIMIT_CORE,
+        }) as libc::c_int
+    }
+}
+
+// a limit which the process likely died from hitting
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ResourceLimitHit {
+    pub kind:       ResourceLimitKind,
+    pub value:      u64,
+    // whether the signal the process was terminated with doesn't conclusively mean the limit was hit
+    pub likely:     bool,
+}
+
+impl ResourceLimitHit {
+    pub fn description(&self) -> String {
+        format!("{}hit its {} limit ({})", if self.likely { "likely " } else { "" }, self.kind.description(),
+                    self.kind.format_value(self.value))
+    }
+
+    pub fn metadata_value(&self) -> String {
+        format!("{}{}", self.kind.name(), if self.likely { " (likely)" } else { "" })
+    }
+}
+
+// setrlimit() limits to apply to started processes. Sizes are in bytes, and CPU time is in seconds.
+#[derive(Clone, Debug, Default)]
+pub struct ResourceLimits {
+    pub limits:         Vec<(ResourceLimitKind, u64)>,
+}
+
+impl ResourceLimits {
+    pub fn is_empty(&self) -> bool {
+        self.limits.is_empty()
+    }
+
+    pub fn get(&self, kind: ResourceLimitKind) -> Option<u64> {
+        self.limits.iter().find(|(limit_kind, _)| *limit_kind == kind).map(|(_, value)| *value)
+    }
+
+    pub fn set(&mut self, kind: ResourceLimitKind, value: u64) {
+        self.limits.retain(|(limit_kind, _)| *limit_kind != kind);
+        self.limits.push((kind, value));
+    }
+
+    // checks the limits can be applied, which they can't be if they're above the current hard limits, as only the
+    // soft limits are set.
+    #[cfg(unix)]
+    pub fn check(&self) -> bool {
+        for (kind, value) in &self.limits {
+            let Some((_, hard_limit)) = get_rlimit(kind.resource()) else {
+                eprintln!("Error: couldn't get the current {} limit.", kind.description());
+                return false;
+            };
+            if hard_limit != libc::RLIM_INFINITY && *value > hard_limit {
+                eprintln!("Error: {} limit of {} is above the current hard limit of {}, so it can't be applied.",
+                            kind.description(), kind.format_value(*value), kind.format_value(hard_limit));
+                return false;
+            }
+        }
+        true
+    }
+
+    #[cfg(not(unix))]
+    pub fn check(&self) -> bool {
+        if !self.is_empty() {
+            eprintln!("Error: resource limits are only supported on Unix platforms.");
+            return false;
+        }
+        true
+    }
+
+    // applies the limits to the current process. This is called between fork() and exec(), so it must only use
+    // async-signal-safe functions. Only the soft limits are set to the values (the hard limits are left as they are, as
+    // raising them needs privileges), so that (i.e.) SIGXCPU is sent when the CPU time limit is hit, rather than SIGKILL.
+    #[cfg(unix)]
+    pub fn apply(&self) -> std::io::Result<()> {
+        for (kind, value) in &self.limits {
+            let Some((_, hard_limit)) = get_rlimit(kind.resource()) else {
+                return Err(std::io::Error::last_os_error());
+            };
+            // the values have already been checked against the hard limits, but they can't be above them regardless
+            let rlimit = libc::rlimit { rlim_cur: (*value).min(hard_limit), rlim_max: hard_limit };
+            if unsafe { libc::setrlimit(kind.resource() as _, &rlimit) } != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    // works out whether the process died from hitting one of the limits from how it exited. Hitting the CPU time
+    // limit results in SIGXCPU, but hitting the memory limits just makes allocations fail, which usually results in
+    // the process aborting or crashing, so that can only be a guess.
+    pub fn limit_hit(&self, exit_info: &ExitInfo, oom_verdict: OomVerdict) -> Option<ResourceLimitHit> {
+        let signal = exit_info.signal?;
+
+        if signal == SIGXCPU {
+            if let Some(value) = self.get(ResourceLimitKind::CpuTime) {
+                return Some(ResourceLimitHit { kind: ResourceLimitKind::CpuTime, value, likely: false });
+            }
+        }
+
+        if [SIGSEGV, SIGBUS, SIGABRT, SIGKILL].contains(&signal) && oom_verdict == OomVerdict::NotKilled {
+            for kind in [ResourceLimitKind::AddressSpace, ResourceLimitKind::Data] {
+                if let Some(value) = self.get(kind) {
+                    return Some(ResourceLimitHit { kind, value, likely: true });
+                }
+            }
+        }
+
+        None
+    }
+
+    pub fn metadata_items(&self) -> Vec<(&'static str, String)> {
+        self.limits.iter().map(|(kind, value)| ("launchlimit", format!("{}={}", kind.name(), value))).collect()
+    }
+}
+
+#[cfg(unix)]
+fn get_rlimit(resource: libc::c_int) -> Option<(libc::rlim_t, libc::rlim_t)> {
+    let mut rlimit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    if unsafe { libc::getrlimit(resource as _, &mut rlimit) } != 0 {
+        return None;
+    }
+    Some((rlimit.rlim_cur, rlimit.rlim_max))
+}
+
+#[cfg(unix)]
+use libc::{SIGABRT, SIGBUS, SIGKILL, SIGSEGV, SIGXCPU};
+#[cfg(not(unix))]
+const SIGABRT: i32 = 6;
+#[cfg(not(unix))]
+const SIGBUS: i32 = 7;
+#[cfg(not(unix))]
+const SIGKILL: i32 = 9;
+#[cfg(not(unix))]
+const SIGSEGV: i32 = 11;
+#[cfg(not(unix))]
+const SIGXCPU: i32 = 24;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limit_hit() {
+        let mut limits = ResourceLimits::default();
+        limits.set(ResourceLimitKind::CpuTime, 10);
+
+        let killed_by = |signal: i32| ExitInfo { signal: Some(signal), ..Default::default() };
+
+        assert_eq!(limits.limit_hit(&killed_by(SIGXCPU), OomVerdict::NotKilled),
+                   Some(ResourceLimitHit { kind: ResourceLimitKind::CpuTime, value: 10, likely: false }));
+        // without a memory limit, a crash is just a crash
+        assert_eq!(limits.limit_hit(&killed_by(SIGSEGV), OomVerdict::NotKilled), None);
+
+        limits.set(ResourceLimitKind::AddressSpace, 1024 * 1024);
+        assert_eq!(limits.limit_hit(&killed_by(SIGSEGV), OomVerdict::NotKilled),
+                   Some(ResourceLimitHit { kind: ResourceLimitKind::AddressSpace, value: 1024 * 1024, likely: true }));
+        assert_eq!(limits.limit_hit(&killed_by(SIGKILL), OomVerdict::Killed), None);
+        assert_eq!(limits.limit_hit(&ExitInfo { exit_code: Some(1), ..Default::default() }, OomVerdict::NotKilled), None);
+    }
+}
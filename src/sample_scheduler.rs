@@ -0,0 +1,192 @@
+/*
+ psrec
+ Copyright 2022-2024 Peter Pearson.
+ Licensed under the Apache License, Version 2.0 (the "License");
+ You may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+ http://www.apache.org/licenses/LICENSE-2.0
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+ ---------
+*/
+
+use std::time::{Duration, Instant};
+
+// Statistics about how accurately the samples were taken at the scheduled times.
+#[derive(Clone, Debug, Default)]
+pub struct SchedulerStats {
+    // number of ticks that were waited for (not including the initial sample at the start)
+    pub ticks:              u64,
+
+    // number of ticks that were skipped, due to sampling (or the system) being too slow to keep up
+    pub missed_ticks:       u64,
+
+    // in seconds: how late after the scheduled time each tick actually happened
+    pub jitter_sum:         f64,
+    pub jitter_sum_sq:      f64,
+    pub max_jitter:         f64,
+}
+
+impl SchedulerStats {
+    pub fn mean_jitter(&self) -> f64 {
+        if self.ticks == 0 {
+            return 0.0;
+        }
+        self.jitter_sum / self.ticks as f64
+    }
+
+    pub fn jitter_std_dev(&self) -> f64 {
+        if self.ticks == 0 {
+            return 0.0;
+        }
+        let mean = self.mean_jitter();
+        (self.jitter_sum_sq / self.ticks as f64 - mean * mean).max(0.0).sqrt()
+    }
+
+    pub fn metadata_items(&self) -> Vec<(&'static str, String)> {
+        vec![("scheduledticks", self.ticks.to_string()),
+             ("missedticks", self.missed_ticks.to_string()),
+             ("jittermeanms", format!("{:.3}", self.mean_jitter() * 1000.0)),
+             ("jitterstddevms", format!("{:.3}", self.jitter_std_dev() * 1000.0)),
+             ("jittermaxms", format!("{:.3}", self.max_jitter * 1000.0))]
+    }
+}
+
+// Schedules samples at absolute deadlines (multiples of the interval from the start time), rather than
+// sleeping for the interval after each sample, so that the time taken to take each sample (and any
+// oversleeping) doesn't accumulate as drift over long recordings.
+// With an adaptive sample interval, the interval is the minimum one, and samples are only taken every
+// tick_step ticks, so that samples are still always taken at multiples of the minimum interval.
+pub struct SampleScheduler {
+    start:          Instant,
+    interval:       Duration,
+
+    // index of the last tick, with tick 0 being the start time
+    last_tick:      u64,
+    // number of ticks between the last tick and the next one to wait for
+    tick_step:      u64,
+
+    stats:          SchedulerStats,
+}
+
+impl SampleScheduler {
+    pub fn new(start: Instant, interval: Duration) -> SampleScheduler {
+        SampleScheduler { start, interval, last_tick: 0, tick_step: 1, stats: SchedulerStats::default() }
+    }
+
+    // sets the number of (minimum) intervals until the next tick
+    pub fn set_tick_step(&mut self, tick_step: u64) {
+        self.tick_step = tick_step.max(1);
+    }
+
+    pub fn stats(&self) -> &SchedulerStats {
+        &self.stats
+    }
+
+    // sleeps until the next deadline. If we're already late by one or more whole intervals, those
+    // ticks are skipped (and counted as missed) rather than being sampled back-to-back to catch up,
+    // but if we're late by less than an interval, this returns immediately so the sample is taken now.
+    pub fn wait_for_next_tick(&mut self) {
+        if let Some(sleep_duration) = self.time_until_next_deadline(self.start.elapsed()) {
+            std::thread::sleep(sleep_duration);
+        }
+
+        self.record_tick(self.start.elapsed());
+    }
+
+    // works out how long to sleep for until the next deadline given the current time since the start,
+    // skipping any ticks which are already more than an interval in the past.
+    fn time_until_next_deadline(&mut self, elapsed: Duration) -> Option<Duration> {
+        let deadline = self.deadline(self.next_tick());
+        if elapsed < deadline {
+            return Some(deadline - elapsed);
+        }
+
+        let ticks_late = ((elapsed - deadline).as_nanos() / self.interval.as_nanos()) as u64;
+        if ticks_late > 0 {
+            self.stats.missed_ticks += ticks_late;
+            self.last_tick += ticks_late;
+        }
+
+        None
+    }
+
+    // records the stats for the tick actually happening at the elapsed time since the start
+    fn record_tick(&mut self, elapsed: Duration) {
+        let jitter = elapsed.saturating_sub(self.deadline(self.next_tick())).as_secs_f64();
+
+        self.stats.ticks += 1;
+        self.stats.jitter_sum += jitter;
+        self.stats.jitter_sum_sq += jitter * jitter;
+        self.stats.max_jitter = self.stats.max_jitter.max(jitter);
+
+        self.last_tick = self.next_tick();
+    }
+
+    fn next_tick(&self) -> u64 {
+        self.last_tick + self.tick_step
+    }
+
+    fn deadline(&self, tick: u64) -> Duration {
+        Duration::from_nanos((self.interval.as_nanos() * tick as u128) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scheduler_sleeps_until_absolute_deadline() {
+        let mut scheduler = SampleScheduler::new(Instant::now(), Duration::from_millis(100));
+
+        // sampling took 30ms, so we should only sleep for the remaining 70ms
+        assert_eq!(scheduler.time_until_next_deadline(Duration::from_millis(30)), Some(Duration::from_millis(70)));
+        scheduler.record_tick(Duration::from_millis(102));
+
+        // and the next deadline is at 200ms, regardless of the previous tick being late
+        assert_eq!(scheduler.time_until_next_deadline(Duration::from_millis(150)), Some(Duration::from_millis(50)));
+        scheduler.record_tick(Duration::from_millis(200));
+
+        assert_eq!(scheduler.stats().ticks, 2);
+        assert_eq!(scheduler.stats().missed_ticks, 0);
+        assert!((scheduler.stats().max_jitter - 0.002).abs() < 1e-9);
+        assert!((scheduler.stats().mean_jitter() - 0.001).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_scheduler_skips_missed_ticks() {
+        let mut scheduler = SampleScheduler::new(Instant::now(), Duration::from_millis(100));
+
+        // late by less than an interval: sample straight away, without skipping
+        assert_eq!(scheduler.time_until_next_deadline(Duration::from_millis(150)), None);
+        scheduler.record_tick(Duration::from_millis(150));
+        assert_eq!(scheduler.stats().missed_ticks, 0);
+
+        // the deadline for the next tick was 200ms, so at 430ms we've missed the 200ms and 300ms ticks,
+        // and should sample immediately for the 400ms tick
+        assert_eq!(scheduler.time_until_next_deadline(Duration::from_millis(430)), None);
+        scheduler.record_tick(Duration::from_millis(430));
+        assert_eq!(scheduler.stats().missed_ticks, 2);
+
+        // back on schedule for the 500ms tick
+        assert_eq!(scheduler.time_until_next_deadline(Duration::from_millis(440)), Some(Duration::from_millis(60)));
+    }
+
+    #[test]
+    fn test_scheduler_tick_step() {
+        let mut scheduler = SampleScheduler::new(Instant::now(), Duration::from_millis(100));
+
+        scheduler.set_tick_step(4);
+        assert_eq!(scheduler.time_until_next_deadline(Duration::from_millis(10)), Some(Duration::from_millis(390)));
+        scheduler.record_tick(Duration::from_millis(400));
+
+        // stepping back down to every tick continues from the last tick
+        scheduler.set_tick_step(1);
+        assert_eq!(scheduler.time_until_next_deadline(Duration::from_millis(420)), Some(Duration::from_millis(80)));
+        assert_eq!(scheduler.stats().missed_ticks, 0);
+    }
+}
@@ -0,0 +1,203 @@
+/*
+ psrec
+ Copyright 2022-2024 Peter Pearson.
+ Licensed under the Apache License, Version 2.0 (the "License");
+ You may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+ http://www.apache.org/licenses/LICENSE-2.0
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+ ---------
+*/
+
+use crate::process_samples::Sample;
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// conditions which trigger capturing samples into the recording in trigger mode
+#[derive(Clone, Debug)]
+pub enum TriggerCondition {
+    // the CPU usage (absolute %) is above the threshold
+    CpuAbove(f32),
+    // the RSS (in bytes) is above the threshold
+    RssAbove(u64),
+    // the marker file exists (it's removed once it has triggered, so it can be created again to trigger again)
+    MarkerFile(PathBuf),
+}
+
+impl TriggerCondition {
+    // short description, as written to the recording metadata
+    pub fn description(&self) -> String {
+        match self {
+            TriggerCondition::CpuAbove(threshold) =>    format!("cpu>{:.1}", threshold),
+            TriggerCondition::RssAbove(threshold) =>    format!("rss>{}", threshold),
+            TriggerCondition::MarkerFile(_) =>          "marker".to_string(),
+        }
+    }
+
+    fn is_met(&self, sample: Option<&Sample>) -> bool {
+        match self {
+            TriggerCondition::CpuAbove(threshold) =>    sample.is_some_and(|sample| sample.cpu_usage > *threshold),
+            TriggerCondition::RssAbove(threshold) =>    sample.is_some_and(|sample| sample.curr_rss > *threshold),
+            TriggerCondition::MarkerFile(path) => {
+                if path.exists() {
+                    // remove it, so that it can be used to trigger again
+                    let _ = std::fs::remove_file(path);
+                    true
+                }
+                else {
+                    false
+                }
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TriggerParams {
+    // capturing is triggered when any of these are met
+    pub conditions:     Vec<TriggerCondition>,
+
+    // how much history from before the trigger to keep
+    pub pre_trigger:    Duration,
+
+    // how long to keep capturing for after the conditions stop being met
+    pub post_trigger:   Duration,
+}
+
+// when capturing was triggered, as marked on the recording timeline
+#[derive(Clone, Debug)]
+pub struct TriggerEvent {
+    // in seconds, since the start of the recording
+    pub elapsed_time:   f64,
+
+    // description of the condition which triggered it
+    pub condition:      String,
+}
+
+impl TriggerEvent {
+    // returns the event formatted as the value of a CSV metadata item
+    pub fn metadata_value(&self, time_precision: usize) -> String {
+        format!("{:.*},{}", time_precision, self.elapsed_time, self.condition)
+    }
+}
+
+// Keeps the most recent samples in a (time-bounded) ring buffer, and only passes samples on to be committed
+// to the recording once a trigger condition is met, along with the buffered pre-trigger history, and then all
+// samples until the post-trigger time has passed since the conditions were last met.
+pub struct SampleTrigger {
+    params:             TriggerParams,
+
+    // samples (with the index of the series they're for) from before any trigger, oldest first
+    buffer:             VecDeque<(usize, Sample)>,
+
+    // elapsed time (in seconds) samples should be captured until, if currently capturing
+    capture_until:      Option<f64>,
+
+    // allowance (in seconds) for the jitter of sample times when working out which samples are within the pre/post-trigger
+    // times, so that samples scheduled exactly at the boundaries aren't dropped if they're taken slightly late.
+    time_tolerance:     f64,
+
+    events:             Vec<TriggerEvent>,
+}
+
+impl SampleTrigger {
+    pub fn new(params: TriggerParams, sample_interval: Duration) -> SampleTrigger {
+        SampleTrigger { params, buffer: VecDeque::new(), capture_until: None, time_tolerance: sample_interval.as_secs_f64() / 2.0,
+                        events: Vec::new() }
+    }
+
+    pub fn events(&self) -> &[TriggerEvent] {
+        &self.events
+    }
+
+    // processes the samples taken at a sample time (with the total of them to check the conditions against), and
+    // returns the samples which should now be committed to the recording, with the rest being buffered.
+    pub fn process_samples(&mut self, elapsed_time: f64, total_sample: Option<&Sample>, samples: Vec<(usize, Sample)>) -> Vec<(usize, Sample)> {
+        self.trim_buffer(elapsed_time);
+
+        let met_condition = self.params.conditions.iter().find(|condition| condition.is_met(total_sample));
+
+        if let Some(condition) = met_condition {
+            if self.capture_until.is_none() {
+                eprintln!("Trigger condition met ({}) at {:.2} secs, capturing samples...", condition.description(), elapsed_time);
+                self.events.push(TriggerEvent { elapsed_time, condition: condition.description() });
+            }
+            self.capture_until = Some(elapsed_time + self.params.post_trigger.as_secs_f64());
+        }
+
+        match self.capture_until {
+            Some(capture_until) if elapsed_time <= capture_until + self.time_tolerance => {
+                // commit any pre-trigger history first
+                let mut commit_samples: Vec<(usize, Sample)> = self.buffer.drain(..).collect();
+                commit_samples.extend(samples);
+                commit_samples
+            },
+            _ => {
+                if self.capture_until.take().is_some() {
+                    eprintln!("Finished capturing triggered samples at {:.2} secs.", elapsed_time);
+                }
+
+                self.buffer.extend(samples);
+                Vec::new()
+            }
+        }
+    }
+
+    // drops any buffered samples older than the pre-trigger history we need to keep
+    fn trim_buffer(&mut self, elapsed_time: f64) {
+        let oldest_time = elapsed_time - self.params.pre_trigger.as_secs_f64() - self.time_tolerance;
+        while self.buffer.front().is_some_and(|(_, sample)| sample.elapsed_time < oldest_time) {
+            self.buffer.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // processes a sample of a single series for each of the cpu usage values (one per second), and returns
+    // the times of the samples which were committed.
+    fn committed_times(trigger: &mut SampleTrigger, cpu_values: &[f32]) -> Vec<f64> {
+        let mut times = Vec::new();
+        for (index, cpu_usage) in cpu_values.iter().enumerate() {
+            let sample = Sample::for_test(index as f64, *cpu_usage, 0);
+            let committed = trigger.process_samples(sample.elapsed_time, Some(&sample), vec![(0, sample.clone())]);
+            times.extend(committed.iter().map(|(_, sample)| sample.elapsed_time));
+        }
+        times
+    }
+
+    #[test]
+    fn test_sample_trigger_windows() {
+        let params = TriggerParams { conditions: vec![TriggerCondition::CpuAbove(50.0)],
+                                     pre_trigger: Duration::from_secs(2), post_trigger: Duration::from_secs(1) };
+        let mut trigger = SampleTrigger::new(params, Duration::from_secs(1));
+
+        let times = committed_times(&mut trigger, &[0.0, 0.0, 0.0, 0.0, 90.0, 0.0, 0.0, 0.0, 0.0, 80.0, 80.0, 0.0, 0.0, 0.0]);
+        // 2 secs before and 1 sec after each spike, with the second spike extended while the condition holds
+        assert_eq!(times, vec![2.0, 3.0, 4.0, 5.0, 7.0, 8.0, 9.0, 10.0, 11.0]);
+
+        let event_times: Vec<f64> = trigger.events().iter().map(|event| event.elapsed_time).collect();
+        assert_eq!(event_times, vec![4.0, 9.0]);
+        assert_eq!(trigger.events()[0].condition, "cpu>50.0");
+    }
+
+    #[test]
+    fn test_sample_trigger_not_triggered() {
+        let params = TriggerParams { conditions: vec![TriggerCondition::RssAbove(1024)],
+                                     pre_trigger: Duration::from_secs(3), post_trigger: Duration::from_secs(3) };
+        let mut trigger = SampleTrigger::new(params, Duration::from_secs(1));
+
+        assert!(committed_times(&mut trigger, &[100.0; 20]).is_empty());
+        // the buffer should only hold the pre-trigger history
+        assert_eq!(trigger.buffer.len(), 4);
+        assert!(trigger.events().is_empty());
+    }
+}
@@ -0,0 +1,109 @@
+/*
+ psrec
+ Copyright 2022-2024 Peter Pearson.
+ Licensed under the Apache License, Version 2.0 (the "License");
+ You may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+ http://www.apache.org/licenses/LICENSE-2.0
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+ ---------
+*/
+
+// summary statistics of a set of values (i.e. the same metric over multiple benchmark runs)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stats {
+    pub count:          usize,
+    pub mean:           f64,
+    pub median:         f64,
+    // sample standard deviation (0 for a single value)
+    pub stddev:         f64,
+    pub min:            f64,
+    pub max:            f64,
+
+    // half the width of the 95% confidence interval of the mean (0 for a single value)
+    pub ci95:           f64,
+}
+
+impl Stats {
+    pub fn from_values(values: &[f64]) -> Option<Stats> {
+        if values.is_empty() {
+            return None;
+        }
+
+        let count = values.len();
+        let mean = values.iter().sum::<f64>() / count as f64;
+
+        let mut sorted_values = values.to_vec();
+        sorted_values.sort_by(|a, b| a.total_cmp(b));
+        // Note: usize::is_multiple_of() needs a much newer Rust version than we otherwise require.
+        #[allow(clippy::manual_is_multiple_of)]
+        let median = if count % 2 == 0 {
+            (sorted_values[count / 2 - 1] + sorted_values[count / 2]) / 2.0
+        }
+        else {
+            sorted_values[count / 2]
+        };
+
+        let (stddev, ci95) = if count > 1 {
+            let variance = values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / (count - 1) as f64;
+            let stddev = variance.sqrt();
+            (stddev, t_critical_value_95(count - 1) * stddev / (count as f64).sqrt())
+        }
+        else {
+            (0.0, 0.0)
+        };
+
+        Some(Stats { count, mean, median, stddev, min: sorted_values[0], max: sorted_values[count - 1], ci95 })
+    }
+
+    pub fn ci95_low(&self) -> f64 {
+        self.mean - self.ci95
+    }
+
+    pub fn ci95_high(&self) -> f64 {
+        self.mean + self.ci95
+    }
+}
+
+// returns the two-tailed critical value of Student's t-distribution for a 95% confidence interval with the degrees
+// of freedom, as benchmarks normally have too few runs to use the normal distribution.
+fn t_critical_value_95(degrees_of_freedom: usize) -> f64 {
+    const VALUES: [f64; 30] = [12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228,
+                               2.201, 2.179, 2.160, 2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086,
+                               2.080, 2.074, 2.069, 2.064, 2.060, 2.056, 2.052, 2.048, 2.045, 2.042];
+    match degrees_of_freedom {
+        0 =>            f64::INFINITY,
+        1..=30 =>       VALUES[degrees_of_freedom - 1],
+        31..=60 =>      2.000,
+        61..=120 =>     1.980,
+        _ =>            1.960,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_from_values() {
+        assert_eq!(Stats::from_values(&[]), None);
+
+        let stats = Stats::from_values(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]).unwrap();
+        assert_eq!(stats.count, 8);
+        assert_eq!(stats.mean, 5.0);
+        assert_eq!(stats.median, 4.5);
+        assert_eq!((stats.min, stats.max), (2.0, 9.0));
+        assert!((stats.stddev - (32.0f64 / 7.0).sqrt()).abs() < 1e-9);
+        assert!((stats.ci95 - 2.365 * stats.stddev / 8.0f64.sqrt()).abs() < 1e-9);
+
+        let stats = Stats::from_values(&[3.0, 1.0, 2.0]).unwrap();
+        assert_eq!(stats.median, 2.0);
+
+        let stats = Stats::from_values(&[1.5]).unwrap();
+        assert_eq!((stats.mean, stats.median, stats.stddev, stats.ci95), (1.5, 1.5, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,169 @@
+/*
+ psrec
+ Copyright 2022-2024 Peter Pearson.
+ Licensed under the Apache License, Version 2.0 (the "License");
+ You may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+ http://www.apache.org/licenses/LICENSE-2.0
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+ ---------
+*/
+
+use crate::process_samples::Sample;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+
+// the conditions which can end a recording. Whichever is met first stops the recording.
+#[derive(Clone, Debug)]
+pub enum StopCondition {
+    // the process being recorded has exited
+    TargetExited,
+    // the flag has been set (by the Ctrl+C handler)
+    Cancelled(Arc<AtomicBool>),
+    // the recording has been going for the duration
+    Duration(Duration),
+    // the CPU usage of the process (in absolute %) has been at or below the threshold for the duration
+    Idle { cpu_threshold: f32, duration: Duration },
+    // the RSS of the process (in bytes) is above the threshold
+    RssAbove(u64),
+    // the wallclock time has reached the deadline
+    Deadline(DateTime<Local>),
+}
+
+// which condition ended a recording
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopReason {
+    TargetExited,
+    Cancelled,
+    DurationReached,
+    Idle,
+    RssAbove,
+    DeadlineReached,
+}
+
+impl StopReason {
+    // short identifier, as written to the recording metadata
+    pub fn name(&self) -> &'static str {
+        match self {
+            StopReason::TargetExited =>     "exited",
+            StopReason::Cancelled =>        "cancelled",
+            StopReason::DurationReached =>  "duration",
+            StopReason::Idle =>             "idle",
+            StopReason::RssAbove =>         "rss",
+            StopReason::DeadlineReached =>  "deadline",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            StopReason::TargetExited =>     "Recorded process has exited",
+            StopReason::Cancelled =>        "Manually cancelled",
+            StopReason::DurationReached =>  "Recording duration limit reached",
+            StopReason::Idle =>             "Recorded process has been idle for the idle time limit",
+            StopReason::RssAbove =>         "Recorded process' RSS has exceeded the RSS limit",
+            StopReason::DeadlineReached =>  "Recording deadline reached",
+        }
+    }
+}
+
+// checks a list of stop conditions while recording, keeping track of any state needed to do that.
+pub struct StopConditionChecker {
+    conditions:     Vec<StopCondition>,
+
+    // elapsed time (in seconds) the process first became idle at, if it currently is
+    idle_since:     Option<f64>,
+}
+
+impl StopConditionChecker {
+    pub fn new(conditions: Vec<StopCondition>) -> StopConditionChecker {
+        StopConditionChecker { conditions, idle_since: None }
+    }
+
+    fn reason_for_condition(condition: &StopCondition) -> StopReason {
+        match condition {
+            StopCondition::TargetExited =>      StopReason::TargetExited,
+            StopCondition::Cancelled(_) =>      StopReason::Cancelled,
+            StopCondition::Duration(_) =>       StopReason::DurationReached,
+            StopCondition::Idle { .. } =>       StopReason::Idle,
+            StopCondition::RssAbove(_) =>       StopReason::RssAbove,
+            StopCondition::Deadline(_) =>       StopReason::DeadlineReached,
+        }
+    }
+
+    // returns the reason to stop recording if any of the conditions have been met, given whether the target is still
+    // running, the latest sample (if one was taken) and the elapsed time since the start of the recording.
+    pub fn check(&mut self, target_running: bool, latest_sample: Option<&Sample>, elapsed: Duration) -> Option<StopReason> {
+        for condition in &self.conditions {
+            let met = match condition {
+                StopCondition::TargetExited =>          !target_running,
+                StopCondition::Cancelled(flag) =>       flag.load(Ordering::SeqCst),
+                StopCondition::Duration(duration) =>    elapsed >= *duration,
+                StopCondition::Idle { cpu_threshold, duration } => {
+                    if let Some(sample) = latest_sample {
+                        if sample.cpu_usage <= *cpu_threshold {
+                            let idle_since = *self.idle_since.get_or_insert(sample.elapsed_time);
+                            sample.elapsed_time - idle_since >= duration.as_secs_f64()
+                        }
+                        else {
+                            self.idle_since = None;
+                            false
+                        }
+                    }
+                    else {
+                        false
+                    }
+                },
+                StopCondition::RssAbove(threshold) =>   latest_sample.is_some_and(|sample| sample.curr_rss > *threshold),
+                StopCondition::Deadline(deadline) =>    Local::now() >= *deadline,
+            };
+
+            if met {
+                return Some(Self::reason_for_condition(condition));
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stop_condition_idle() {
+        let mut checker = StopConditionChecker::new(vec![StopCondition::Idle { cpu_threshold: 1.0, duration: Duration::from_secs(2) }]);
+
+        assert_eq!(checker.check(true, Some(&Sample::for_test(0.0, 0.5, 0)), Duration::from_secs(0)), None);
+        assert_eq!(checker.check(true, Some(&Sample::for_test(1.0, 0.5, 0)), Duration::from_secs(1)), None);
+        // busy again, so the idle time should be reset
+        assert_eq!(checker.check(true, Some(&Sample::for_test(2.0, 50.0, 0)), Duration::from_secs(2)), None);
+        assert_eq!(checker.check(true, Some(&Sample::for_test(3.0, 0.0, 0)), Duration::from_secs(3)), None);
+        assert_eq!(checker.check(true, Some(&Sample::for_test(4.0, 0.0, 0)), Duration::from_secs(4)), None);
+        assert_eq!(checker.check(true, Some(&Sample::for_test(5.0, 0.0, 0)), Duration::from_secs(5)), Some(StopReason::Idle));
+    }
+
+    #[test]
+    fn test_stop_condition_order() {
+        let cancel_flag = Arc::new(AtomicBool::new(false));
+        let mut checker = StopConditionChecker::new(vec![StopCondition::TargetExited,
+                                                         StopCondition::Cancelled(cancel_flag.clone()),
+                                                         StopCondition::Duration(Duration::from_secs(10)),
+                                                         StopCondition::RssAbove(1024)]);
+
+        assert_eq!(checker.check(true, Some(&Sample::for_test(1.0, 0.0, 1024)), Duration::from_secs(1)), None);
+        assert_eq!(checker.check(true, Some(&Sample::for_test(2.0, 0.0, 2048)), Duration::from_secs(2)), Some(StopReason::RssAbove));
+        assert_eq!(checker.check(true, None, Duration::from_secs(10)), Some(StopReason::DurationReached));
+        cancel_flag.store(true, Ordering::SeqCst);
+        assert_eq!(checker.check(true, None, Duration::from_secs(10)), Some(StopReason::Cancelled));
+        assert_eq!(checker.check(false, None, Duration::from_secs(10)), Some(StopReason::TargetExited));
+    }
+}
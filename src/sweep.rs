@@ -0,0 +1,270 @@
+/*
+ psrec
+ Copyright 2022-2024 Peter Pearson.
+ Licensed under the Apache License, Version 2.0 (the "License");
+ You may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+ http://www.apache.org/licenses/LICENSE-2.0
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+ ---------
+*/
+
+use crate::bench::{bench_run_export_path, run_benchmark, BenchError, BenchParams, BenchRunMetrics};
+use crate::launch_info::LaunchInfo;
+use crate::process_recorder::ProcessRecordParams;
+use crate::process_samples::ProcessRecording;
+use crate::stats::Stats;
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+// a parameter to run the command with each of the values of, which is substituted into the command where '{NAME}'
+// is, and set as an environment variable.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SweepParam {
+    pub name:       String,
+    pub values:     Vec<String>,
+}
+
+impl SweepParam {
+    // parses a parameter in the form NAME=VALUES, where the values are a comma-separated list of values and/or
+    // integer ranges, in the form START..END (inclusive) or START..END:STEP, i.e. 'THREADS=1..4,8,16'.
+    // Items are only treated as ranges if they're entirely integers, so other values containing '..' (i.e. relative
+    // paths like '../data') are used as they are.
+    pub fn parse(str_val: &str) -> Option<SweepParam> {
+        let (name, values_string) = str_val.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return None;
+        }
+
+        let mut values = Vec::new();
+        for item in values_string.split(',') {
+            let item = item.trim();
+            if item.is_empty() {
+                return None;
+            }
+            match parse_range(item) {
+                Some((start, end, step)) => {
+                    if step <= 0 || end < start {
+                        return None;
+                    }
+                    values.extend((start..=end).step_by(step as usize).map(|value| value.to_string()));
+                },
+                None => values.push(item.to_string()),
+            }
+        }
+
+        Some(SweepParam { name: name.to_string(), values })
+    }
+
+    // returns how to launch the process for the value of the parameter
+    pub fn launch_info(&self, base_launch_info: &LaunchInfo, value: &str) -> LaunchInfo {
+        let placeholder = format!("{{{}}}", self.name);
+        let mut launch_info = base_launch_info.clone();
+        launch_info.argv = base_launch_info.argv.iter().map(|arg| arg.replace(&placeholder, value)).collect();
+        launch_info.set_env(&self.name, value);
+        launch_info
+    }
+}
+
+// parses an integer range in the form START..END or START..END:STEP, returning the start, end and step
+fn parse_range(item: &str) -> Option<(i64, i64, i64)> {
+    let (start, end) = item.split_once("..")?;
+    let (end, step) = match end.split_once(':') {
+        Some((end, step)) =>    (end, step.trim().parse::<i64>().ok()?),
+        None =>                 (end, 1),
+    };
+    Some((start.trim().parse::<i64>().ok()?, end.trim().parse::<i64>().ok()?, step))
+}
+
+// the metrics of the recorded runs of the command for one value of the parameter
+pub struct SweepVariant {
+    pub value:                  String,
+    pub metrics:                Vec<BenchRunMetrics>,
+
+    // the system thread count of the recordings, which CPU efficiency is relative to unless it's normalised against
+    // a specific thread count
+    pub num_system_threads:     u32,
+}
+
+impl SweepVariant {
+    // returns the stats of the CPU efficiency of the runs, which is the percentage of the CPU time of all the threads
+    // (of the system, or the thread count normalised against) which was used over the wall time.
+    fn cpu_efficiency_stats(&self, thread_count: u32) -> Option<Stats> {
+        let values: Vec<f64> = self.metrics.iter().map(|run| run.mean_cpu / thread_count.max(1) as f64).collect();
+        Stats::from_values(&values)
+    }
+}
+
+// runs the benchmark for each value of the parameter.
+// run_recorded is called with the value and (zero-based) index of each run as soon as it's finished, and
+// variant_finished with the results of each value once all its runs have finished (so the caller keeps the values
+// which did finish if a later one fails), either of which can return false to stop the sweep, i.e. if exporting failed.
+pub fn run_sweep(base_launch_info: &LaunchInfo, sweep_param: &SweepParam, bench_params: &BenchParams,
+                 record_params: &ProcessRecordParams, cancel_flag: Arc<AtomicBool>,
+                 mut run_recorded: impl FnMut(&str, usize, &ProcessRecording) -> bool,
+                 mut variant_finished: impl FnMut(SweepVariant) -> bool) -> Result<(), BenchError> {
+    for value in &sweep_param.values {
+        let launch_info = sweep_param.launch_info(base_launch_info, value);
+        eprintln!("Sweep {}={}: {}", sweep_param.name, value, launch_info.argv_string());
+
+        let mut metrics = Vec::with_capacity(bench_params.runs);
+        let mut num_system_threads = 1;
+        run_benchmark(&launch_info, bench_params, record_params, cancel_flag.clone(), |run_index, recording| {
+            metrics.extend(BenchRunMetrics::from_recording(recording));
+            num_system_threads = recording.num_system_threads;
+            run_recorded(value, run_index, recording)
+        })?;
+
+        if !variant_finished(SweepVariant { value: value.clone(), metrics, num_system_threads }) {
+            return Err(BenchError::ExportFailed);
+        }
+    }
+    Ok(())
+}
+
+// prints a table of the mean (and 95% confidence interval) of each metric for each value of the parameter
+pub fn print_sweep_summary(variants: &[SweepVariant], sweep_param: &SweepParam, bench_params: &BenchParams, thread_count: u32) {
+    eprintln!("\nSweep results ({} runs of each value, mean +/- 95% CI, CPU efficiency relative to {} thread{}):",
+              bench_params.runs, thread_count, if thread_count == 1 { "" } else { "s" });
+    let name_width = variants.iter().map(|variant| variant.value.len()).chain(std::iter::once(sweep_param.name.len())).max().unwrap_or(0);
+    eprintln!("  {:<name_width$}  {:>20}  {:>20}  {:>20}  {:>20}", sweep_param.name, "Wall time (s)", "Peak RSS (MB)",
+              "CPU time (s)", "CPU efficiency (%)", name_width = name_width);
+
+    let format_stats = |stats: Option<Stats>, precision: usize| {
+        stats.map_or(String::new(), |stats| format!("{:.*} +/- {:.*}", precision, stats.mean, precision, stats.ci95))
+    };
+    for variant in variants {
+        let wall_time = Stats::from_values(&variant.metrics.iter().map(|run| run.wall_time).collect::<Vec<f64>>());
+        let peak_rss = Stats::from_values(&variant.metrics.iter().map(|run| run.peak_rss as f64 / (1024.0 * 1024.0)).collect::<Vec<f64>>());
+        let cpu_time = Stats::from_values(&variant.metrics.iter().map(|run| run.cpu_time).collect::<Vec<f64>>());
+        eprintln!("  {:<name_width$}  {:>20}  {:>20}  {:>20}  {:>20}", variant.value, format_stats(wall_time, 3),
+                  format_stats(peak_rss, 2), format_stats(cpu_time, 3), format_stats(variant.cpu_efficiency_stats(thread_count), 1),
+                  name_width = name_width);
+    }
+}
+
+// CSV file of the mean/stddev/95% CI of each metric for each value of the parameter, which psrec_gen_plot.py can
+// plot. The row of each value is appended (and flushed) as soon as its runs have finished, so the file has the
+// results of the values which did finish even if a later one fails.
+pub struct SweepSummaryFile {
+    path:           String,
+    buf_writer:     BufWriter<File>,
+    thread_count:   u32,
+}
+
+impl SweepSummaryFile {
+    // creates the file and writes the header
+    pub fn create(path: &str, sweep_param: &SweepParam, thread_count: u32) -> Option<SweepSummaryFile> {
+        let result = File::create(path).and_then(|file| {
+            let mut buf_writer = BufWriter::new(file);
+            write_sweep_summary_header(&mut buf_writer, sweep_param, thread_count)?;
+            Ok(buf_writer)
+        });
+        match result {
+            Ok(buf_writer) => Some(SweepSummaryFile { path: path.to_string(), buf_writer, thread_count }),
+            Err(err) => {
+                eprintln!("Error saving sweep summary to CSV file: {}, {}", path, err);
+                None
+            }
+        }
+    }
+
+    pub fn append_variant(&mut self, variant: &SweepVariant) -> bool {
+        let result = write_sweep_summary_row(&mut self.buf_writer, variant, self.thread_count).and_then(|_| self.buf_writer.flush());
+        if let Err(err) = result {
+            eprintln!("Error saving sweep summary to CSV file: {}, {}", self.path, err);
+            return false;
+        }
+
+        true
+    }
+}
+
+fn write_sweep_summary_header(buf_writer: &mut BufWriter<File>, sweep_param: &SweepParam, thread_count: u32) -> std::io::Result<()> {
+    writeln!(buf_writer, "# Parameter sweep summary.")?;
+    let mut columns = vec![sweep_param.name.clone(), "Runs".to_string()];
+    for metric in ["Wall Time", "Peak RSS", "CPU Time", "CPU Efficiency"] {
+        columns.extend([format!("{} Mean", metric), format!("{} Stddev", metric), format!("{} CI", metric)]);
+    }
+    writeln!(buf_writer, "# {}", columns.join(","))?;
+    writeln!(buf_writer, "#@ sweepparam: {}", sweep_param.name)?;
+    writeln!(buf_writer, "#@ efficiencythreads: {}", thread_count)
+}
+
+fn write_sweep_summary_row(buf_writer: &mut BufWriter<File>, variant: &SweepVariant, thread_count: u32) -> std::io::Result<()> {
+    let mut row = vec![variant.value.clone(), variant.metrics.len().to_string()];
+    let metric_stats = [(Stats::from_values(&variant.metrics.iter().map(|run| run.wall_time).collect::<Vec<f64>>()), 3),
+                        (Stats::from_values(&variant.metrics.iter().map(|run| run.peak_rss as f64).collect::<Vec<f64>>()), 0),
+                        (Stats::from_values(&variant.metrics.iter().map(|run| run.cpu_time).collect::<Vec<f64>>()), 3),
+                        (variant.cpu_efficiency_stats(thread_count), 1)];
+    for (stats, precision) in metric_stats {
+        match stats {
+            Some(stats) =>  row.extend([format!("{:.*}", precision, stats.mean), format!("{:.*}", precision, stats.stddev),
+                                        format!("{:.*}", precision, stats.ci95)]),
+            None =>         row.extend([String::new(), String::new(), String::new()]),
+        }
+    }
+    writeln!(buf_writer, "{}", row.join(","))
+}
+
+// returns the path to export the recording of the run for the value of the parameter to, i.e. results_THREADS-4_run03.csv
+pub fn sweep_run_export_path(export_path: &str, sweep_param: &SweepParam, value: &str, run_index: usize, runs: usize) -> String {
+    let path = Path::new(export_path);
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    // values could contain characters which aren't valid in file names
+    let value: String = value.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' }).collect();
+    let extension = path.extension().map_or(String::new(), |extension| format!(".{}", extension.to_string_lossy()));
+    let variant_export_path = path.with_file_name(format!("{}_{}-{}{}", stem, sweep_param.name, value, extension));
+    bench_run_export_path(&variant_export_path.to_string_lossy(), run_index, runs)
+}
+
+// returns the path to save the sweep summary to, alongside the recordings, i.e. results_sweep.csv
+// Note: the summary is always saved as .csv, whatever the extension of the export path.
+pub fn sweep_summary_path(export_path: &str) -> String {
+    let path = Path::new(export_path);
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    path.with_file_name(format!("{}_sweep.csv", stem)).to_string_lossy().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sweep_param_parse() {
+        let param = SweepParam::parse("THREADS=1,2,4,8,16").unwrap();
+        assert_eq!(param.name, "THREADS");
+        assert_eq!(param.values, vec!["1", "2", "4", "8", "16"]);
+
+        assert_eq!(SweepParam::parse("N=1..4,8").unwrap().values, vec!["1", "2", "3", "4", "8"]);
+        assert_eq!(SweepParam::parse("TILE=16..64:16").unwrap().values, vec!["16", "32", "48", "64"]);
+        assert_eq!(SweepParam::parse("MODE=fast, slow").unwrap().values, vec!["fast", "slow"]);
+        // values which aren't integer ranges are used as they are
+        assert_eq!(SweepParam::parse("DIR=../data,../other").unwrap().values, vec!["../data", "../other"]);
+        assert_eq!(SweepParam::parse("VER=1..x").unwrap().values, vec!["1..x"]);
+
+        assert_eq!(SweepParam::parse("THREADS"), None);
+        assert_eq!(SweepParam::parse("=1,2"), None);
+        assert_eq!(SweepParam::parse("N=1,,2"), None);
+        assert_eq!(SweepParam::parse("N=4..1"), None);
+        assert_eq!(SweepParam::parse("N=1..4:0"), None);
+    }
+
+    #[test]
+    fn test_sweep_param_launch_info() {
+        let param = SweepParam::parse("THREADS=1,2").unwrap();
+        let base_launch_info = LaunchInfo::new(vec!["render".to_string(), "--threads={THREADS}".to_string()], false);
+        let launch_info = param.launch_info(&base_launch_info, "2");
+        assert_eq!(launch_info.argv, vec!["render", "--threads=2"]);
+        assert_eq!(launch_info.env, vec![("THREADS".to_string(), "2".to_string())]);
+    }
+}
@@ -0,0 +1,190 @@
+/*
+ psrec
+ Copyright 2022-2024 Peter Pearson.
+ Licensed under the Apache License, Version 2.0 (the "License");
+ You may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+ http://www.apache.org/licenses/LICENSE-2.0
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+ ---------
+*/
+
+use crate::utils::quote_command_line_arg;
+
+// environment variable names containing any of these (case-insensitively) will have their values redacted by default
+pub const DEFAULT_ENV_REDACTION_PATTERNS: &[&str] = &["PASSWORD", "PASSWD", "SECRET", "TOKEN", "KEY", "CREDENTIAL", "AUTH"];
+
+pub const REDACTED_ENV_VALUE: &str = "<redacted>";
+
+// Identifying details about the process being recorded, so that it's possible to know afterwards exactly
+// which process a recording was made of (especially in attach mode).
+#[derive(Clone, Debug, Default)]
+pub struct TargetInfo {
+    pub pid:            u32,
+
+    pub cmdline:        Vec<String>,
+
+    // path to the executable
+    pub exe:            String,
+
+    // current working directory of the process at the time it was attached to/started
+    pub cwd:            String,
+
+    // real user and group IDs
+    pub uid:            Option<u32>,
+    pub gid:            Option<u32>,
+
+    pub user:           String,
+
+    // only recorded if requested, with values of any matching the redaction patterns redacted.
+    pub environment:    Option<Vec<(String, String)>>,
+}
+
+impl TargetInfo {
+    // Note: any of the details which can't be read (e.g. due to permissions for processes owned by other users)
+    //       are left empty.
+    pub fn gather(pid: u32, record_environment: bool, redaction_patterns: &[String]) -> TargetInfo {
+        let mut target_info = TargetInfo { pid, ..Default::default() };
+
+        #[cfg(target_os = "linux")]
+        target_info.gather_linux(record_environment, redaction_patterns);
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            // the redaction patterns only apply when recording the environment
+            let _ = redaction_patterns;
+            if record_environment {
+                eprintln!("Warning: Recording the environment of processes is only supported on Linux.");
+            }
+        }
+
+        target_info
+    }
+
+    #[cfg(target_os = "linux")]
+    fn gather_linux(&mut self, record_environment: bool, redaction_patterns: &[String]) {
+        let process = procfs::process::Process::new(self.pid as i32);
+        if let Err(err) = process {
+            eprintln!("Warning: Couldn't access process details for PID: {}, {}", self.pid, err);
+            return;
+        }
+        let process = process.unwrap();
+
+        if let Ok(cmdline) = process.cmdline() {
+            self.cmdline = cmdline;
+        }
+        if let Ok(exe) = process.exe() {
+            self.exe = exe.display().to_string();
+        }
+        if let Ok(cwd) = process.cwd() {
+            self.cwd = cwd.display().to_string();
+        }
+        if let Ok(status) = process.status() {
+            self.uid = Some(status.ruid);
+            self.gid = Some(status.rgid);
+            self.user = user_name_from_uid(status.ruid).unwrap_or_default();
+        }
+
+        if record_environment {
+            match process.environ() {
+                Ok(environ) => {
+                    let mut environment: Vec<(String, String)> = environ.iter().map(|(key, value)| {
+                        let key = key.to_string_lossy().to_string();
+                        let value = if should_redact_env_var(&key, redaction_patterns) { REDACTED_ENV_VALUE.to_string() }
+                                                                                      else { value.to_string_lossy().to_string() };
+                        (key, value)
+                    }).collect();
+                    environment.sort();
+                    self.environment = Some(environment);
+                },
+                Err(err) => {
+                    eprintln!("Warning: Couldn't read environment of process PID: {}, {}", self.pid, err);
+                }
+            }
+        }
+    }
+
+    // returns the command line as a single string, quoted as needed
+    pub fn cmdline_string(&self) -> String {
+        self.cmdline.iter().map(|arg| quote_command_line_arg(arg)).collect::<Vec<String>>().join(" ")
+    }
+
+    // returns the info as key/value pairs, for file formats which can only store metadata as strings.
+    pub fn metadata_items(&self) -> Vec<(&'static str, String)> {
+        let mut items = vec![("targetpid", self.pid.to_string()),
+                             ("targetcmdline", self.cmdline_string()),
+                             ("targetexe", self.exe.clone()),
+                             ("targetcwd", self.cwd.clone())];
+        if let Some(uid) = self.uid {
+            items.push(("targetuid", uid.to_string()));
+        }
+        if let Some(gid) = self.gid {
+            items.push(("targetgid", gid.to_string()));
+        }
+        if !self.user.is_empty() {
+            items.push(("targetuser", self.user.clone()));
+        }
+        if let Some(environment) = &self.environment {
+            for (key, value) in environment {
+                items.push(("targetenv", format!("{}={}", key, value)));
+            }
+        }
+
+        items
+    }
+}
+
+// the patterns are matched case-insensitively against whole '_' separated components of the variable name (i.e. KEY
+// matches AWS_SECRET_KEY, but not KEYBOARD_LAYOUT or MONKEY_PATH), or as a suffix of it (i.e. APIKEY).
+pub fn should_redact_env_var(name: &str, redaction_patterns: &[String]) -> bool {
+    let name = name.to_ascii_uppercase();
+    let name_components: Vec<&str> = name.split('_').collect();
+    redaction_patterns.iter().any(|pattern| {
+        let pattern = pattern.to_ascii_uppercase();
+        let pattern_components: Vec<&str> = pattern.split('_').collect();
+        name.ends_with(&pattern) || name_components.windows(pattern_components.len()).any(|window| window == pattern_components)
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn user_name_from_uid(uid: u32) -> Option<String> {
+    let passwd = std::fs::read_to_string("/etc/passwd").ok()?;
+    for line in passwd.lines() {
+        let items: Vec<&str> = line.split(':').collect();
+        if items.len() > 2 && items[2].parse::<u32>() == Ok(uid) {
+            return Some(items[0].to_string());
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_should_redact_env_var() {
+        let patterns: Vec<String> = DEFAULT_ENV_REDACTION_PATTERNS.iter().map(|pattern| pattern.to_string()).collect();
+
+        assert!(should_redact_env_var("PASSWORD", &patterns));
+        assert!(should_redact_env_var("AWS_SECRET_ACCESS_KEY", &patterns));
+        assert!(should_redact_env_var("github_token", &patterns));
+        assert!(should_redact_env_var("SSH_AUTH_SOCK", &patterns));
+        assert!(should_redact_env_var("OPENAI_APIKEY", &patterns));
+
+        assert!(!should_redact_env_var("HOME", &patterns));
+        assert!(!should_redact_env_var("KEYBOARD_LAYOUT", &patterns));
+        assert!(!should_redact_env_var("MONKEY_PATH", &patterns));
+        assert!(!should_redact_env_var("AUTHOR_NAME", &patterns));
+
+        // patterns with multiple components have to match consecutive components
+        let patterns = vec!["db_pass".to_string()];
+        assert!(should_redact_env_var("PROD_DB_PASS", &patterns));
+        assert!(!should_redact_env_var("DB_USER_PASS", &patterns));
+    }
+}
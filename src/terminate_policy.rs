@@ -0,0 +1,83 @@
+/*
+ psrec
+ Copyright 2022-2024 Peter Pearson.
+ Licensed under the Apache License, Version 2.0 (the "License");
+ You may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+ http://www.apache.org/licenses/LICENSE-2.0
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+ ---------
+*/
+
+use std::time::Duration;
+
+// what to do with a started process when the recording is stopped by something other than the process exiting
+// (i.e. Ctrl+C or the duration limit).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TerminatePolicy {
+    // leave it running
+    Leave,
+    // forward the signal (SIGINT for Ctrl+C, otherwise SIGTERM) to it, and keep recording until it exits
+    Forward,
+    // send SIGTERM to its process group, and SIGKILL if it hasn't exited after the grace period
+    Terminate,
+    // keep recording until it exits by itself
+    Wait,
+}
+
+impl TerminatePolicy {
+    pub fn from_string(str_val: &str) -> Option<TerminatePolicy> {
+        match str_val {
+            "leave" =>      Some(TerminatePolicy::Leave),
+            "forward" =>    Some(TerminatePolicy::Forward),
+            "terminate" =>  Some(TerminatePolicy::Terminate),
+            "wait" =>       Some(TerminatePolicy::Wait),
+            _ =>            None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TerminateParams {
+    pub policy:         TerminatePolicy,
+
+    // with the terminate policy, how long to wait for the process to exit after SIGTERM before sending SIGKILL
+    pub grace_period:   Duration,
+}
+
+impl Default for TerminateParams {
+    fn default() -> TerminateParams {
+        TerminateParams { policy: TerminatePolicy::Leave, grace_period: Duration::from_secs(10) }
+    }
+}
+
+// the signals which can be sent to started processes (on Unix), as a platform-independent enum
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TerminateSignal {
+    Interrupt,
+    Terminate,
+    Kill,
+}
+
+impl TerminateSignal {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TerminateSignal::Interrupt =>   "SIGINT",
+            TerminateSignal::Terminate =>   "SIGTERM",
+            TerminateSignal::Kill =>        "SIGKILL",
+        }
+    }
+
+    #[cfg(unix)]
+    pub fn number(&self) -> i32 {
+        match self {
+            TerminateSignal::Interrupt =>   libc::SIGINT,
+            TerminateSignal::Terminate =>   libc::SIGTERM,
+            TerminateSignal::Kill =>        libc::SIGKILL,
+        }
+    }
+}
@@ -13,6 +13,8 @@
  ---------
 */
 
+use chrono::{DateTime, Local, NaiveDateTime, NaiveTime, TimeZone};
+
 // returns an Option<> tuple of the u64 value in ms, plus a human-readable
 // string representation of the number with units
 pub fn convert_time_period_string_to_ms(str_val: &str) -> Option<(u64, String)> {
@@ -83,11 +85,188 @@ pub fn convert_time_period_string_to_ms(str_val: &str) -> Option<(u64, String)>
     Some((final_time_in_ms, human_readable_string))
 }
 
+// returns the number of decimal places needed to write elapsed times of samples taken at the interval
+// without losing the distinction between them, with a minimum of one decimal place.
+// Note: u64::is_multiple_of() needs a much newer Rust version than we otherwise require.
+#[allow(clippy::manual_is_multiple_of)]
+pub fn default_time_precision(sample_interval_ms: u64) -> usize {
+    if sample_interval_ms % 100 == 0 {
+        1
+    }
+    else if sample_interval_ms % 10 == 0 {
+        2
+    }
+    else {
+        3
+    }
+}
+
+// returns the argument quoted (with single quotes) if it contains chars which would need it on a shell command line,
+// so that command lines can be printed/stored in a form that can be copied and run again.
+pub fn quote_command_line_arg(arg: &str) -> String {
+    if !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c)) {
+        return arg.to_string();
+    }
+
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+// escapes backslashes, newlines and carriage returns in a metadata value (as '\\', '\n' and '\r'), so that it can be
+// written on a single comment line of a CSV file.
+pub fn escape_metadata_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for chr in value.chars() {
+        match chr {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c =>    escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// the reasons a command line string can't be split into args
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SplitCommandLineError {
+    UnterminatedSingleQuote,
+    UnterminatedDoubleQuote,
+    // a backslash at the very end, with nothing to escape
+    TrailingBackslash,
+}
+
+impl SplitCommandLineError {
+    pub fn description(&self) -> &'static str {
+        match self {
+            SplitCommandLineError::UnterminatedSingleQuote =>   "unterminated single quote",
+            SplitCommandLineError::UnterminatedDoubleQuote =>   "unterminated double quote",
+            SplitCommandLineError::TrailingBackslash =>         "trailing backslash",
+        }
+    }
+}
+
+// splits a command line string into args the way a (POSIX) shell would, handling single and double quotes and
+// backslash escapes, but without any expansions.
+pub fn split_command_line(str_val: &str) -> Result<Vec<String>, SplitCommandLineError> {
+    let mut args = Vec::new();
+    let mut current_arg = String::new();
+    // whether there's an arg being built, as quoted args can be empty
+    let mut in_arg = false;
+
+    let mut chars = str_val.chars();
+    while let Some(chr) = chars.next() {
+        match chr {
+            '\\' => {
+                current_arg.push(chars.next().ok_or(SplitCommandLineError::TrailingBackslash)?);
+                in_arg = true;
+            },
+            '\'' => {
+                loop {
+                    match chars.next().ok_or(SplitCommandLineError::UnterminatedSingleQuote)? {
+                        '\'' => break,
+                        quoted_chr => current_arg.push(quoted_chr),
+                    }
+                }
+                in_arg = true;
+            },
+            '"' => {
+                loop {
+                    match chars.next().ok_or(SplitCommandLineError::UnterminatedDoubleQuote)? {
+                        '"' => break,
+                        // within double quotes, backslashes only escape these chars
+                        '\\' => {
+                            let escaped_chr = chars.next().ok_or(SplitCommandLineError::UnterminatedDoubleQuote)?;
+                            if !"\"\\$`".contains(escaped_chr) {
+                                current_arg.push('\\');
+                            }
+                            current_arg.push(escaped_chr);
+                        },
+                        quoted_chr => current_arg.push(quoted_chr),
+                    }
+                }
+                in_arg = true;
+            },
+            _ if chr.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current_arg));
+                    in_arg = false;
+                }
+            },
+            _ => {
+                current_arg.push(chr);
+                in_arg = true;
+            }
+        }
+    }
+
+    if in_arg {
+        args.push(current_arg);
+    }
+
+    Ok(args)
+}
+
+// returns the size in bytes of a size string with an optional (case-insensitive) unit suffix (K/M/G, with an optional
+// trailing 'B', in multiples of 1024). Not specifying a suffix unit char will use bytes.
+pub fn convert_size_string_to_bytes(str_val: &str) -> Option<u64> {
+    let str_val = str_val.trim().to_ascii_uppercase();
+    let str_val = str_val.strip_suffix('B').unwrap_or(&str_val);
+
+    let (value_string, multiplier) = match str_val.chars().last() {
+        Some('K') =>    (&str_val[..str_val.len() - 1], 1024),
+        Some('M') =>    (&str_val[..str_val.len() - 1], 1024 * 1024),
+        Some('G') =>    (&str_val[..str_val.len() - 1], 1024 * 1024 * 1024),
+        _ =>            (str_val, 1),
+    };
+
+    let value = value_string.trim().parse::<u64>().ok()?;
+    if value == 0 {
+        return None;
+    }
+
+    value.checked_mul(multiplier)
+}
+
+// returns the local date/time of a deadline string, which can either be a time of day ("HH:MM" or "HH:MM:SS",
+// which will be the next occurrence of that time after 'now'), a local date and time ("YYYY-MM-DD HH:MM[:SS]"),
+// or an RFC 3339 timestamp.
+pub fn parse_deadline_string(str_val: &str, now: &DateTime<Local>) -> Option<DateTime<Local>> {
+    let str_val = str_val.trim();
+
+    if let Ok(time) = NaiveTime::parse_from_str(str_val, "%H:%M:%S").or_else(|_| NaiveTime::parse_from_str(str_val, "%H:%M")) {
+        let mut date = now.date_naive();
+        if time <= now.time() {
+            // it's already passed today, so it must be tomorrow
+            date = date.succ_opt()?;
+        }
+        return Local.from_local_datetime(&date.and_time(time)).earliest();
+    }
+
+    if let Ok(date_time) = NaiveDateTime::parse_from_str(str_val, "%Y-%m-%d %H:%M:%S")
+                                        .or_else(|_| NaiveDateTime::parse_from_str(str_val, "%Y-%m-%d %H:%M")) {
+        return Local.from_local_datetime(&date_time).earliest();
+    }
+
+    DateTime::parse_from_rfc3339(str_val).ok().map(|date_time| date_time.with_timezone(&Local))
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_split_command_line() {
+        assert_eq!(split_command_line("prog arg1  arg2"), Ok(vec!["prog".to_string(), "arg1".to_string(), "arg2".to_string()]));
+        assert_eq!(split_command_line(r#"prog "arg two" 'it''s' "" a\ b"#),
+                   Ok(vec!["prog".to_string(), "arg two".to_string(), "its".to_string(), "".to_string(), "a b".to_string()]));
+        assert_eq!(split_command_line(r#"echo "say \"hi\" \n" '\n'"#),
+                   Ok(vec!["echo".to_string(), "say \"hi\" \\n".to_string(), "\\n".to_string()]));
+        assert_eq!(split_command_line("prog 'unterminated"), Err(SplitCommandLineError::UnterminatedSingleQuote));
+        assert_eq!(split_command_line(r#"prog "unterminated \""#), Err(SplitCommandLineError::UnterminatedDoubleQuote));
+        assert_eq!(split_command_line("prog arg\\"), Err(SplitCommandLineError::TrailingBackslash));
+        assert_eq!(split_command_line("  "), Ok(Vec::new()));
+    }
+
     #[test]
     fn test_convert_time_period_string_01_ms() {
         assert_eq!(convert_time_period_string_to_ms("42ms"), Some((42, "42 ms".into())));
@@ -124,4 +303,58 @@ mod tests {
         assert_eq!(convert_time_period_string_to_ms("ms"), None);
         assert_eq!(convert_time_period_string_to_ms("3345nk"), None);
     }
+
+    #[test]
+    fn test_default_time_precision() {
+        assert_eq!(default_time_precision(1000), 1);
+        assert_eq!(default_time_precision(60 * 1000), 1);
+        assert_eq!(default_time_precision(100), 1);
+        assert_eq!(default_time_precision(250), 2);
+        assert_eq!(default_time_precision(10), 2);
+        assert_eq!(default_time_precision(5), 3);
+        assert_eq!(default_time_precision(1), 3);
+    }
+
+    #[test]
+    fn test_quote_command_line_arg() {
+        assert_eq!(quote_command_line_arg("/usr/bin/psrec"), "/usr/bin/psrec");
+        assert_eq!(quote_command_line_arg("-i=250ms"), "-i=250ms");
+        assert_eq!(quote_command_line_arg("arg two"), "'arg two'");
+        assert_eq!(quote_command_line_arg("it's"), "'it'\\''s'");
+        assert_eq!(quote_command_line_arg(""), "''");
+    }
+
+    #[test]
+    fn test_escape_metadata_value() {
+        assert_eq!(escape_metadata_value("sleep 1"), "sleep 1");
+        assert_eq!(escape_metadata_value("line1\nline2,3,4"), "line1\\nline2,3,4");
+        assert_eq!(escape_metadata_value("a\r\nb"), "a\\r\\nb");
+        assert_eq!(escape_metadata_value("C:\\tmp\\n"), "C:\\\\tmp\\\\n");
+    }
+
+    #[test]
+    fn test_convert_size_string_to_bytes() {
+        assert_eq!(convert_size_string_to_bytes("512"), Some(512));
+        assert_eq!(convert_size_string_to_bytes("4K"), Some(4 * 1024));
+        assert_eq!(convert_size_string_to_bytes("4kb"), Some(4 * 1024));
+        assert_eq!(convert_size_string_to_bytes("500M"), Some(500 * 1024 * 1024));
+        assert_eq!(convert_size_string_to_bytes("2G"), Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(convert_size_string_to_bytes("0M"), None);
+        assert_eq!(convert_size_string_to_bytes("M"), None);
+        assert_eq!(convert_size_string_to_bytes("12T"), None);
+    }
+
+    #[test]
+    fn test_parse_deadline_string() {
+        let now = Local.with_ymd_and_hms(2024, 3, 10, 12, 30, 0).unwrap();
+
+        assert_eq!(parse_deadline_string("14:00", &now), Local.with_ymd_and_hms(2024, 3, 10, 14, 0, 0).earliest());
+        assert_eq!(parse_deadline_string("14:00:30", &now), Local.with_ymd_and_hms(2024, 3, 10, 14, 0, 30).earliest());
+        // already passed today, so should be tomorrow
+        assert_eq!(parse_deadline_string("09:15", &now), Local.with_ymd_and_hms(2024, 3, 11, 9, 15, 0).earliest());
+        assert_eq!(parse_deadline_string("2024-03-12 08:00", &now), Local.with_ymd_and_hms(2024, 3, 12, 8, 0, 0).earliest());
+        assert_eq!(parse_deadline_string("2024-03-12T08:00:00Z", &now).map(|date_time| date_time.timestamp()), Some(1710230400));
+        assert_eq!(parse_deadline_string("25:00", &now), None);
+        assert_eq!(parse_deadline_string("tomorrow", &now), None);
+    }
 }